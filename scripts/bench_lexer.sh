@@ -0,0 +1,23 @@
+#!/usr/bin/env bash
+# Time the interpreter on generated data files of growing size. With a linear
+# lexer the time doubles along with the input.
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+cargo build --quiet --release
+data=$(mktemp -d)
+trap 'rm -rf "$data"' EXIT
+
+for n in 100000 200000 400000 800000; do
+    file="$data/data$n.rlisp"
+    # A single quoted list of symbols, numbers and non ASCII strings
+    {
+        printf '(quote ('
+        seq 1 "$n" | awk '{ printf "sym%d %d.5 \"é%d\" ", $1, $1, $1 }'
+        printf '))\n'
+    } > "$file"
+    start=$(date +%s%N)
+    ./target/release/rs_lisp "$file" > /dev/null
+    end=$(date +%s%N)
+    printf '%8d items %10d bytes %6d ms\n' "$n" "$(wc -c < "$file")" $(((end - start) / 1000000))
+done
@@ -0,0 +1,488 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::eval::{EvalError, Result};
+use crate::value::{Builtin, Condition, ConditionType, ErrorObject, Value};
+
+/// Procedures available in the global environment
+#[rustfmt::skip]
+pub const BUILTINS: &[Builtin] = &[
+    Builtin { name: "+", func: add },
+    Builtin { name: "-", func: sub },
+    Builtin { name: "*", func: mul },
+    Builtin { name: "/", func: div },
+    Builtin { name: "==", func: num_eq },
+    Builtin { name: "/=", func: num_neq },
+    Builtin { name: "<", func: lt },
+    Builtin { name: ">", func: gt },
+    Builtin { name: "<=", func: le },
+    Builtin { name: ">=", func: ge },
+    Builtin { name: "not", func: not },
+    Builtin { name: "null?", func: is_null },
+    Builtin { name: "pair?", func: is_pair },
+    EQV,
+    Builtin { name: "cons", func: cons },
+    Builtin { name: "car", func: car },
+    Builtin { name: "cdr", func: cdr },
+    LIST,
+    APPEND,
+    LIST_TO_VECTOR,
+    Builtin { name: "display", func: display },
+    Builtin { name: "write", func: write },
+    Builtin { name: "newline", func: newline },
+    Builtin { name: "error-object?", func: is_error_object },
+    Builtin { name: "error-object-message", func: error_object_message },
+    Builtin { name: "error-object-irritants", func: error_object_irritants },
+    Builtin { name: "error-object-location", func: error_object_location },
+    Builtin { name: "make-condition", func: make_condition },
+    Builtin { name: "condition?", func: is_condition },
+    Builtin { name: "condition-of-type?", func: is_condition_of_type },
+    Builtin { name: "condition-slot", func: condition_slot },
+];
+
+/// Identity comparison, also used by the analysis of `case`
+pub const EQV: Builtin = Builtin {
+    name: "eqv?",
+    func: eqv,
+};
+
+/// List construction, also used by the analysis of `quasiquote`
+pub const LIST: Builtin = Builtin {
+    name: "list",
+    func: list,
+};
+
+/// List concatenation, also used by the analysis of `quasiquote`
+pub const APPEND: Builtin = Builtin {
+    name: "append",
+    func: append,
+};
+
+/// Vector construction from a list, also used by the analysis of
+/// `quasiquote`
+pub const LIST_TO_VECTOR: Builtin = Builtin {
+    name: "list->vector",
+    func: list_to_vector,
+};
+
+/// Creation of condition types, used by the analysis of `define-condition`
+pub const MAKE_CONDITION_TYPE: Builtin = Builtin {
+    name: "make-condition-type",
+    func: make_condition_type,
+};
+
+thread_local! {
+    /// Type every condition belongs to, extended by the types without an
+    /// explicit parent
+    static CONDITION: Rc<ConditionType> = Rc::new(ConditionType {
+        name: "condition".into(),
+        parent: None,
+        slots: vec![],
+    });
+}
+
+/// Root of the hierarchy of condition types
+pub fn condition_type() -> Rc<ConditionType> {
+    CONDITION.with(Rc::clone)
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Operand of the arithmetic builtins
+enum Number {
+    Exact(i64),
+    Inexact(f64),
+}
+
+impl Number {
+    fn inexact(self) -> f64 {
+        match self {
+            Number::Exact(n) => n as f64,
+            Number::Inexact(n) => n,
+        }
+    }
+
+    fn value(self) -> Value {
+        match self {
+            Number::Exact(n) => Value::Integer(n),
+            Number::Inexact(n) => Value::Number(n),
+        }
+    }
+}
+
+/// Extract the numbers from the arguments of the builtin `name`
+fn numbers(name: &str, args: &[Value]) -> Result<Vec<Number>> {
+    args.iter()
+        .map(|arg| match arg {
+            Value::Integer(n) => Ok(Number::Exact(*n)),
+            Value::Number(n) => Ok(Number::Inexact(*n)),
+            _ => Err(EvalError::new(format!(
+                "`{}` expects numbers, got {}",
+                name, arg
+            ))),
+        })
+        .collect()
+}
+
+/// Check that the builtin `name` received exactly `n` arguments
+fn arity(name: &str, args: &[Value], n: usize) -> Result<()> {
+    if args.len() == n {
+        Ok(())
+    } else {
+        Err(EvalError::new(format!(
+            "`{}` expects {} arguments, got {}",
+            name,
+            n,
+            args.len()
+        )))
+    }
+}
+
+/// Apply an arithmetic operation, which stays exact as long as both operands
+/// are exact and the result fits in 64 bits
+fn combine(
+    a: Number,
+    b: Number,
+    exact: fn(i64, i64) -> Option<i64>,
+    inexact: fn(f64, f64) -> f64,
+) -> Number {
+    match (a, b) {
+        (Number::Exact(a), Number::Exact(b)) => match exact(a, b) {
+            Some(n) => Number::Exact(n),
+            None => Number::Inexact(inexact(a as f64, b as f64)),
+        },
+        _ => Number::Inexact(inexact(a.inexact(), b.inexact())),
+    }
+}
+
+fn add(args: &[Value]) -> Result<Value> {
+    let numbers = numbers("+", args)?.into_iter();
+    let sum = numbers.fold(Number::Exact(0), |acc, x| {
+        combine(acc, x, i64::checked_add, |a, b| a + b)
+    });
+    Ok(sum.value())
+}
+
+fn mul(args: &[Value]) -> Result<Value> {
+    let numbers = numbers("*", args)?.into_iter();
+    let product = numbers.fold(Number::Exact(1), |acc, x| {
+        combine(acc, x, i64::checked_mul, |a, b| a * b)
+    });
+    Ok(product.value())
+}
+
+fn sub(args: &[Value]) -> Result<Value> {
+    let difference = |a, b| combine(a, b, i64::checked_sub, |a, b| a - b);
+    match numbers("-", args)?.split_first() {
+        None => Err(EvalError::new(String::from(
+            "`-` expects at least one argument",
+        ))),
+        // Unary minus negates its argument
+        Some((n, [])) => Ok(difference(Number::Exact(0), *n).value()),
+        Some((n, rest)) => Ok(rest.iter().fold(*n, |acc, x| difference(acc, *x)).value()),
+    }
+}
+
+fn div(args: &[Value]) -> Result<Value> {
+    match numbers("/", args)?.split_first() {
+        None => Err(EvalError::new(String::from(
+            "`/` expects at least one argument",
+        ))),
+        Some((n, [])) => divide(Number::Exact(1), *n).map(Number::value),
+        Some((n, rest)) => rest
+            .iter()
+            .try_fold(*n, |acc, x| divide(acc, *x))
+            .map(Number::value),
+    }
+}
+
+/// Divide two numbers, failing on division by zero. The quotient of exact
+/// numbers is exact only when the division has no remainder.
+fn divide(n: Number, d: Number) -> Result<Number> {
+    if d.inexact() == 0.0 {
+        return Err(EvalError::new(String::from("division by zero")));
+    }
+    Ok(match (n, d) {
+        (Number::Exact(n), Number::Exact(d)) if n.checked_rem(d) == Some(0) => Number::Exact(n / d),
+        _ => Number::Inexact(n.inexact() / d.inexact()),
+    })
+}
+
+/// Compare two numbers, exactly when both are exact
+fn compare(a: Number, b: Number) -> Option<Ordering> {
+    match (a, b) {
+        (Number::Exact(a), Number::Exact(b)) => Some(a.cmp(&b)),
+        _ => a.inexact().partial_cmp(&b.inexact()),
+    }
+}
+
+fn num_eq(args: &[Value]) -> Result<Value> {
+    monotonic("==", args, |o| o == Ordering::Equal)
+}
+
+fn num_neq(args: &[Value]) -> Result<Value> {
+    let numbers = numbers("/=", args)?;
+    // All the numbers must be pairwise different
+    Ok(Value::Bool(numbers.iter().enumerate().all(|(i, x)| {
+        numbers[i + 1..]
+            .iter()
+            .all(|y| compare(*x, *y) != Some(Ordering::Equal))
+    })))
+}
+
+/// Check that `holds` is true for the ordering of each pair of adjacent
+/// numbers
+fn monotonic(name: &str, args: &[Value], holds: fn(Ordering) -> bool) -> Result<Value> {
+    let numbers = numbers(name, args)?;
+    Ok(Value::Bool(
+        numbers
+            .windows(2)
+            .all(|w| compare(w[0], w[1]).is_some_and(holds)),
+    ))
+}
+
+fn lt(args: &[Value]) -> Result<Value> {
+    monotonic("<", args, |o| o == Ordering::Less)
+}
+
+fn gt(args: &[Value]) -> Result<Value> {
+    monotonic(">", args, |o| o == Ordering::Greater)
+}
+
+fn le(args: &[Value]) -> Result<Value> {
+    monotonic("<=", args, |o| o != Ordering::Greater)
+}
+
+fn ge(args: &[Value]) -> Result<Value> {
+    monotonic(">=", args, |o| o != Ordering::Less)
+}
+
+fn not(args: &[Value]) -> Result<Value> {
+    arity("not", args, 1)?;
+    Ok(Value::Bool(!args[0].is_true()))
+}
+
+fn is_null(args: &[Value]) -> Result<Value> {
+    arity("null?", args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Nil)))
+}
+
+fn is_pair(args: &[Value]) -> Result<Value> {
+    arity("pair?", args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Pair(_))))
+}
+
+fn eqv(args: &[Value]) -> Result<Value> {
+    arity("eqv?", args, 2)?;
+    Ok(Value::Bool(args[0].eqv(&args[1])))
+}
+
+fn cons(args: &[Value]) -> Result<Value> {
+    arity("cons", args, 2)?;
+    Ok(Value::cons(args[0].clone(), args[1].clone()))
+}
+
+fn car(args: &[Value]) -> Result<Value> {
+    arity("car", args, 1)?;
+    match &args[0] {
+        Value::Pair(pair) => Ok(pair.car.borrow().clone()),
+        other => Err(EvalError::new(format!(
+            "`car` expects a pair, got {}",
+            other
+        ))),
+    }
+}
+
+fn cdr(args: &[Value]) -> Result<Value> {
+    arity("cdr", args, 1)?;
+    match &args[0] {
+        Value::Pair(pair) => Ok(pair.cdr.borrow().clone()),
+        other => Err(EvalError::new(format!(
+            "`cdr` expects a pair, got {}",
+            other
+        ))),
+    }
+}
+
+fn list(args: &[Value]) -> Result<Value> {
+    Ok(Value::list(args.to_vec()))
+}
+
+/// Copy all the lists but the last one, which becomes the shared tail of
+/// the result and can be any value
+fn append(args: &[Value]) -> Result<Value> {
+    let Some((last, lists)) = args.split_last() else {
+        return Ok(Value::Nil);
+    };
+    let mut items = vec![];
+    for list in lists {
+        items.extend(list.to_vec()?);
+    }
+    Ok(items
+        .into_iter()
+        .rev()
+        .fold(last.clone(), |tail, item| Value::cons(item, tail)))
+}
+
+fn list_to_vector(args: &[Value]) -> Result<Value> {
+    arity("list->vector", args, 1)?;
+    Ok(Value::Vector(args[0].to_vec()?.into()))
+}
+
+/// Print a value for humans, showing strings and chars as they are
+fn display(args: &[Value]) -> Result<Value> {
+    arity("display", args, 1)?;
+    print!("{}", args[0].display());
+    Ok(Value::Nil)
+}
+
+/// Print a value so that the reader can read it back
+fn write(args: &[Value]) -> Result<Value> {
+    arity("write", args, 1)?;
+    print!("{}", args[0]);
+    Ok(Value::Nil)
+}
+
+fn newline(args: &[Value]) -> Result<Value> {
+    arity("newline", args, 0)?;
+    println!();
+    Ok(Value::Nil)
+}
+
+fn is_error_object(args: &[Value]) -> Result<Value> {
+    arity("error-object?", args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Error(_))))
+}
+
+/// Extract the error object passed to the builtin `name`
+fn error_object<'a>(name: &str, args: &'a [Value]) -> Result<&'a ErrorObject> {
+    arity(name, args, 1)?;
+    match &args[0] {
+        Value::Error(error) => Ok(error),
+        other => Err(EvalError::new(format!(
+            "`{}` expects an error object, got {}",
+            name, other
+        ))),
+    }
+}
+
+fn error_object_message(args: &[Value]) -> Result<Value> {
+    let error = error_object("error-object-message", args)?;
+    Ok(Value::Str(error.message.clone()))
+}
+
+fn error_object_irritants(args: &[Value]) -> Result<Value> {
+    let error = error_object("error-object-irritants", args)?;
+    Ok(error.irritants.clone())
+}
+
+/// Line and column where the failing expression starts, `nil` when unknown
+fn error_object_location(args: &[Value]) -> Result<Value> {
+    let error = error_object("error-object-location", args)?;
+    Ok(match error.position {
+        Some(position) => Value::list(vec![
+            Value::Integer(position.line.into()),
+            Value::Integer(position.column.into()),
+        ]),
+        None => Value::Nil,
+    })
+}
+
+/// Called as `(make-condition-type name parent slots)`, where a `nil` parent
+/// stands for the root type
+fn make_condition_type(args: &[Value]) -> Result<Value> {
+    arity("make-condition-type", args, 3)?;
+    let (name, parent, own) = match args {
+        [Value::Symbol(name), parent, own] => (name.clone(), parent, own.to_vec()?),
+        _ => return Err(EvalError::new("malformed condition type")),
+    };
+    let parent = match parent {
+        Value::Nil => condition_type(),
+        Value::ConditionType(parent) => parent.clone(),
+        other => return Err(EvalError::new(format!("{} is not a condition type", other))),
+    };
+    let mut slots = parent.slots.clone();
+    for slot in own {
+        match slot {
+            Value::Symbol(slot) if !slots.contains(&slot) => slots.push(slot),
+            other => {
+                return Err(EvalError::new(format!(
+                    "invalid or duplicated slot {} in condition {}",
+                    other, name
+                )))
+            }
+        }
+    }
+    Ok(Value::ConditionType(Rc::new(ConditionType {
+        name,
+        parent: Some(parent),
+        slots,
+    })))
+}
+
+/// Extract the condition type passed to the builtin `name`
+fn condition_kind<'a>(name: &str, arg: &'a Value) -> Result<&'a Rc<ConditionType>> {
+    match arg {
+        Value::ConditionType(kind) => Ok(kind),
+        other => Err(EvalError::new(format!(
+            "`{}` expects a condition type, got {}",
+            name, other
+        ))),
+    }
+}
+
+/// Called as `(make-condition type value...)`, with a value for each slot
+fn make_condition(args: &[Value]) -> Result<Value> {
+    let (kind, values) = args
+        .split_first()
+        .ok_or_else(|| EvalError::new("`make-condition` expects at least one argument"))?;
+    let kind = condition_kind("make-condition", kind)?;
+    if values.len() != kind.slots.len() {
+        return Err(EvalError::new(format!(
+            "condition {} expects {} slot values, got {}",
+            kind.name,
+            kind.slots.len(),
+            values.len()
+        )));
+    }
+    Ok(Value::Condition(Rc::new(Condition {
+        kind: kind.clone(),
+        values: values.to_vec(),
+    })))
+}
+
+fn is_condition(args: &[Value]) -> Result<Value> {
+    arity("condition?", args, 1)?;
+    Ok(Value::Bool(matches!(args[0], Value::Condition(_))))
+}
+
+fn is_condition_of_type(args: &[Value]) -> Result<Value> {
+    arity("condition-of-type?", args, 2)?;
+    let kind = condition_kind("condition-of-type?", &args[1])?;
+    Ok(Value::Bool(match &args[0] {
+        Value::Condition(condition) => condition.kind.is_subtype(kind),
+        _ => false,
+    }))
+}
+
+/// Called as `(condition-slot condition name)`
+fn condition_slot(args: &[Value]) -> Result<Value> {
+    arity("condition-slot", args, 2)?;
+    match (&args[0], &args[1]) {
+        (Value::Condition(condition), Value::Symbol(slot)) => condition
+            .kind
+            .slots
+            .iter()
+            .position(|name| name == slot)
+            .map(|i| condition.values[i].clone())
+            .ok_or_else(|| {
+                EvalError::new(format!(
+                    "condition {} has no slot {}",
+                    condition.kind.name, slot
+                ))
+            }),
+        _ => Err(EvalError::new(format!(
+            "`condition-slot` expects a condition and a slot name, got {} and {}",
+            args[0], args[1]
+        ))),
+    }
+}
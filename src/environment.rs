@@ -0,0 +1,236 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::value::{Closure, Pair, Value};
+
+#[derive(Debug, Clone, Default)]
+/// Chain of lexical frames, cloning it only clones the handle to the
+/// innermost frame
+pub struct Environment(Rc<Frame>);
+
+#[derive(Debug, Default)]
+/// Single scope holding its own bindings and a link to the enclosing one
+struct Frame {
+    bindings: RefCell<HashMap<Rc<str>, Value>>,
+    parent: Option<Environment>,
+    /// Whether the frame is tracked by a `Collector`
+    tracked: Cell<bool>,
+}
+
+impl Environment {
+    /// Create an empty top level environment
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a nested scope whose bindings shadow the ones of `self`
+    pub fn extend(&self) -> Self {
+        Environment(Rc::new(Frame {
+            bindings: RefCell::new(HashMap::new()),
+            parent: Some(self.clone()),
+            tracked: Cell::new(false),
+        }))
+    }
+
+    /// Bind `name` in the innermost frame, replacing a previous binding there
+    pub fn define(&self, name: Rc<str>, value: Value) {
+        self.0.bindings.borrow_mut().insert(name, value);
+    }
+
+    /// Look up the value bound to `name`, starting from the innermost frame
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let mut env = self;
+        loop {
+            if let Some(value) = env.0.bindings.borrow().get(name) {
+                return Some(value.clone());
+            }
+            env = env.0.parent.as_ref()?;
+        }
+    }
+
+    /// Replace the value of the closest binding of `name`, returning false if
+    /// the name is not bound at all
+    pub fn set(&self, name: &str, value: Value) -> bool {
+        let mut env = self;
+        loop {
+            if let Some(slot) = env.0.bindings.borrow_mut().get_mut(name) {
+                *slot = value;
+                return true;
+            }
+            match env.0.parent.as_ref() {
+                Some(parent) => env = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Number of frames tracked before looking for garbage the first time
+const FIRST_COLLECTION: usize = 10_000;
+
+#[derive(Default)]
+/// Frames that procedures close over, whose garbage is freed from time to
+/// time. A procedure stored in the frame it closes over, as an internal
+/// definition does, makes a cycle of reference counts that would keep the
+/// frame alive forever. Every such cycle goes through the frame where a
+/// procedure was created, so the other frames are not tracked.
+pub struct Collector {
+    frames: Vec<Weak<Frame>>,
+    /// Number of frames to track before looking for garbage again
+    threshold: usize,
+}
+
+/// Object of the heap that holds references to other ones
+enum Node {
+    Frame(Rc<Frame>),
+    Pair(Rc<Pair>),
+    Vector(Rc<[Value]>),
+    Closure(Rc<Closure>),
+}
+
+impl Node {
+    /// Node of the heap that a value is, if any
+    fn of(value: &Value) -> Option<Node> {
+        match value {
+            Value::Pair(pair) => Some(Node::Pair(pair.clone())),
+            Value::Vector(items) => Some(Node::Vector(items.clone())),
+            Value::Closure(closure) => Some(Node::Closure(closure.clone())),
+            _ => None,
+        }
+    }
+
+    /// Node of the frame of an environment. Top-level frames are never
+    /// garbage, so they are left out along with everything they refer to.
+    fn frame(env: &Environment) -> Option<Node> {
+        env.0.parent.as_ref().map(|_| Node::Frame(env.0.clone()))
+    }
+
+    /// Address identifying the node
+    fn id(&self) -> *const () {
+        match self {
+            Node::Frame(frame) => Rc::as_ptr(frame) as *const (),
+            Node::Pair(pair) => Rc::as_ptr(pair) as *const (),
+            Node::Vector(items) => Rc::as_ptr(items) as *const (),
+            Node::Closure(closure) => Rc::as_ptr(closure) as *const (),
+        }
+    }
+
+    /// Number of references to the node, from anywhere
+    fn references(&self) -> usize {
+        match self {
+            Node::Frame(frame) => Rc::strong_count(frame),
+            Node::Pair(pair) => Rc::strong_count(pair),
+            Node::Vector(items) => Rc::strong_count(items),
+            Node::Closure(closure) => Rc::strong_count(closure),
+        }
+    }
+
+    /// Add the nodes the node holds a reference to to `children`, once per
+    /// reference
+    fn children(&self, children: &mut Vec<Node>) {
+        match self {
+            Node::Frame(frame) => {
+                children.extend(frame.bindings.borrow().values().filter_map(Node::of));
+                children.extend(frame.parent.as_ref().and_then(Node::frame));
+            }
+            Node::Pair(pair) => {
+                children.extend(Node::of(&pair.car.borrow()));
+                children.extend(Node::of(&pair.cdr.borrow()));
+            }
+            Node::Vector(items) => children.extend(items.iter().filter_map(Node::of)),
+            Node::Closure(closure) => children.extend(Node::frame(&closure.env)),
+        }
+    }
+}
+
+impl Collector {
+    /// Keep track of the frame of `env`, where a procedure is created, and
+    /// free the garbage frames when enough were tracked since the last time
+    pub fn track(&mut self, env: &Environment) {
+        if env.0.parent.is_none() || env.0.tracked.replace(true) {
+            return;
+        }
+        self.frames.push(Rc::downgrade(&env.0));
+        if self.frames.len() >= self.threshold.max(FIRST_COLLECTION) {
+            self.collect();
+        }
+    }
+
+    /// Number of frames still alive
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.frames
+            .iter()
+            .filter(|frame| frame.strong_count() > 0)
+            .count()
+    }
+
+    /// Free the frames that can only be reached from themselves.
+    ///
+    /// The objects reachable from the frames are collected along with the
+    /// references they hold to each other. An object with more references
+    /// than that is also referred to from outside, by the machine or by a
+    /// value held elsewhere, so it is alive along with everything it refers
+    /// to. The bindings of the other frames are dropped, which breaks their
+    /// cycles. References that are not followed, such as the ones held by
+    /// continuations, only keep more objects alive.
+    fn collect(&mut self) {
+        // Each node is held once by `nodes`, which adds one to its references
+        let mut nodes: Vec<Node> = self
+            .frames
+            .iter()
+            .filter_map(|frame| frame.upgrade().map(Node::Frame))
+            .collect();
+        let mut index: HashMap<*const (), usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.id(), i))
+            .collect();
+        // References between the nodes, the ones of node `i` being
+        // `edges[ends[i - 1]..ends[i]]`
+        let mut edges = vec![];
+        let mut ends = vec![];
+        let mut internal = vec![0; nodes.len()];
+        let mut children = vec![];
+        let mut i = 0;
+        while i < nodes.len() {
+            nodes[i].children(&mut children);
+            for child in children.drain(..) {
+                let j = *index.entry(child.id()).or_insert_with(|| {
+                    nodes.push(child);
+                    internal.push(0);
+                    nodes.len() - 1
+                });
+                internal[j] += 1;
+                edges.push(j);
+            }
+            ends.push(edges.len());
+            i += 1;
+        }
+        let mut alive = vec![false; nodes.len()];
+        let mut roots: Vec<usize> = (0..nodes.len())
+            .filter(|&i| nodes[i].references() > 1 + internal[i])
+            .collect();
+        while let Some(i) = roots.pop() {
+            if !std::mem::replace(&mut alive[i], true) {
+                let start = if i == 0 { 0 } else { ends[i - 1] };
+                roots.extend(&edges[start..ends[i]]);
+            }
+        }
+        let survivors = alive.iter().filter(|&&alive| alive).count();
+        let mut garbage = vec![];
+        for (node, alive) in nodes.iter().zip(alive) {
+            if let (Node::Frame(frame), false) = (node, alive) {
+                garbage.push(std::mem::take(&mut *frame.bindings.borrow_mut()));
+            }
+        }
+        // The values of the garbage frames are dropped once nothing else
+        // borrows them
+        drop(nodes);
+        drop(garbage);
+        self.frames.retain(|frame| frame.strong_count() > 0);
+        // Walking the survivors again is paid by as many new frames
+        self.threshold = self.frames.len() + survivors;
+    }
+}
@@ -0,0 +1,1653 @@
+use std::rc::Rc;
+
+use crate::builtins::{self, BUILTINS};
+use crate::environment::{Collector, Environment};
+use crate::expr::{self, Expr, Exprs};
+use crate::lexer::Position;
+use crate::parser::{Positions, SExpression};
+use crate::value::{Closure, Condition, ErrorObject, Value};
+
+/// Wrapper to a generic error encountered during the evaluation phase
+pub type Result<T> = std::result::Result<T, EvalError>;
+
+#[derive(Debug, Clone)]
+/// Error while the evaluation phase
+pub struct EvalError {
+    pub message: String,
+    /// Where the failing expression starts in the source, when known
+    pub position: Option<Position>,
+    /// Names and parameters of the restarts that were available when the
+    /// error was not handled, innermost first
+    pub restarts: Vec<Rc<str>>,
+    /// Whether the debugger already showed the error to the user
+    pub reported: bool,
+}
+
+impl EvalError {
+    /// Create an error not bound to any source position
+    pub fn new(message: impl Into<String>) -> Self {
+        EvalError {
+            message: message.into(),
+            position: None,
+            restarts: vec![],
+            reported: false,
+        }
+    }
+
+    /// Create an error raised by the expression starting at `position`
+    pub fn at(message: impl Into<String>, position: Position) -> Self {
+        EvalError {
+            message: message.into(),
+            position: Some(position),
+            restarts: vec![],
+            reported: false,
+        }
+    }
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "Evaluation Error: {} {}", position, self.message)?,
+            None => write!(f, "Evaluation Error: {}", self.message)?,
+        }
+        if !self.restarts.is_empty() {
+            write!(f, "\nAvailable restarts:")?;
+            for (i, restart) in self.restarts.iter().enumerate() {
+                write!(f, "\n  {}: {}", i, restart)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Create the environment with the predefined constants and builtins
+pub fn global_environment() -> Environment {
+    let env = Environment::new();
+    env.define("t".into(), Value::Bool(true));
+    env.define("nil".into(), Value::Nil);
+    env.define(
+        "condition".into(),
+        Value::ConditionType(builtins::condition_type()),
+    );
+    for builtin in BUILTINS {
+        env.define(builtin.name.into(), Value::Builtin(*builtin));
+    }
+    for (name, control) in CONTROLS {
+        env.define((*name).into(), Value::Control(*control));
+    }
+    env
+}
+
+/// Default memory budget of the evaluation stack, in bytes, which also
+/// bounds the native stack used by the analysis
+pub const DEFAULT_STACK_LIMIT: usize = 256 * 1024 * 1024;
+
+/// Native stack used by the analysis for each level of nested lists, at
+/// most. The analysis recurses, so it is only given as many levels as fit in
+/// the memory budget.
+const NESTING_SIZE: usize = if cfg!(debug_assertions) {
+    16 * 1024
+} else {
+    4 * 1024
+};
+
+/// Nesting always allowed, however small the budget, since the native stack
+/// has room for it beyond the budget
+const MIN_NESTING: usize = 64;
+
+/// Frames allowed beyond the budget once it is exceeded, so that the
+/// handlers of the error still have room to run
+const SPARE_FRAMES: usize = 4096;
+
+/// Pending work to resume once the value of a sub-expression is known. The
+/// stack of frames is the continuation of the expression being evaluated.
+#[derive(Clone)]
+enum Frame {
+    Define(Rc<str>, Environment),
+    Set(Rc<str>, Position, Environment),
+    /// Choose between the two branches of an `if`
+    If(Rc<Expr>, Rc<Expr>, Environment),
+    /// Evaluate the expressions of a sequence starting from the index
+    Sequence(Sequence, Exprs, usize, Environment),
+    /// The operator has been evaluated, the operands come next
+    Operator(Exprs, Position, Environment),
+    /// Collect the values of the operands evaluated so far
+    Operands {
+        procedure: Value,
+        args: Vec<Value>,
+        operands: Exprs,
+        position: Position,
+        env: Environment,
+    },
+    /// Target of the escape continuation with the given id, along with the
+    /// dynamic extents and the dynamic state active when it was created
+    Escape(u64, Winders, Dynamic),
+    /// Waiting for the `before` thunk of `dynamic-wind`, then call the
+    /// second thunk within the extent of the first and the third ones
+    DynamicWind(Value, Value, Value),
+    /// Waiting for the body of `dynamic-wind`, then leave its extent
+    Unwind(Rc<Winder>),
+    /// Discard the value of a cleanup thunk and return the saved one
+    Restore(Value),
+    /// Run the thunks while moving between dynamic extents, starting from
+    /// the step at the index, then return the value to the continuation
+    Transfer(Rc<[WindStep]>, usize, Winders, Value),
+    /// Reinstate the dynamic state once the body it was set up for returns
+    Dynamic(Dynamic),
+    /// Waiting for the handler of a non-continuable `raise`, which must not
+    /// return
+    Raise(Value),
+    /// Waiting for a handler of `signal`, which declined to handle the
+    /// condition by returning, then look for the next one
+    Signal {
+        condition: Rc<Condition>,
+        cluster: Rc<Cluster>,
+        /// Index of the next binding of the cluster to try
+        index: usize,
+        /// Dynamic state of the call to `signal`
+        saved: Dynamic,
+        /// Whether the condition comes from `error`, and must be raised
+        /// when no handler takes care of it
+        error: bool,
+    },
+}
+
+/// Innermost exception handler installed by `with-exception-handler`, if any
+type Handlers = Option<Rc<Handler>>;
+
+#[derive(Debug)]
+/// Exception handler along with the ones it was installed within
+pub struct Handler {
+    handler: Value,
+    parent: Handlers,
+}
+
+/// Innermost group of bindings established by `handler-bind` or
+/// `restart-case`, if any
+type Clusters = Option<Rc<Cluster>>;
+
+#[derive(Debug)]
+/// Condition handlers established together, each along with the type of
+/// conditions it handles, or restarts established together, each along with
+/// its name
+pub struct Cluster {
+    bindings: Vec<(Value, Value)>,
+    parent: Clusters,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Handlers and restarts in effect, saved and reinstated along with the stack
+struct Dynamic {
+    handlers: Handlers,
+    conditions: Clusters,
+    restarts: Clusters,
+}
+
+/// Innermost dynamic extent entered with `dynamic-wind`, if any
+type Winders = Option<Rc<Winder>>;
+
+#[derive(Debug)]
+/// Dynamic extent entered with `dynamic-wind`, whose thunks must run each
+/// time control enters or leaves it
+pub struct Winder {
+    before: Value,
+    after: Value,
+    /// Enclosing extent
+    parent: Winders,
+    /// Number of enclosing extents
+    depth: usize,
+}
+
+/// Thunk to call while moving between dynamic extents
+struct WindStep {
+    thunk: Value,
+    /// Extents active while the thunk runs
+    winders: Winders,
+}
+
+/// Number of extents in a chain of winders
+fn depth(winders: &Winders) -> usize {
+    winders.as_ref().map_or(0, |winder| winder.depth + 1)
+}
+
+/// Thunks to call to move from the extents `from` to the extents `to`: the
+/// `after` thunks of the extents being left, innermost first, followed by the
+/// `before` thunks of the extents being entered, outermost first
+fn wind_steps(from: &Winders, to: &Winders) -> Vec<WindStep> {
+    let (mut from, mut to) = (from.clone(), to.clone());
+    let mut leave = vec![];
+    let mut enter = vec![];
+    while depth(&from) > depth(&to) {
+        let winder = from.expect("Never happen!");
+        from = winder.parent.clone();
+        leave.push(winder);
+    }
+    while depth(&to) > depth(&from) {
+        let winder = to.expect("Never happen!");
+        to = winder.parent.clone();
+        enter.push(winder);
+    }
+    // Walk up both chains until their common extent
+    while let (Some(a), Some(b)) = (&from, &to) {
+        if Rc::ptr_eq(a, b) {
+            break;
+        }
+        let (a, b) = (a.clone(), b.clone());
+        from = a.parent.clone();
+        to = b.parent.clone();
+        leave.push(a);
+        enter.push(b);
+    }
+    let leave = leave.into_iter().map(|winder| WindStep {
+        thunk: winder.after.clone(),
+        winders: winder.parent.clone(),
+    });
+    let enter = enter.into_iter().rev().map(|winder| WindStep {
+        thunk: winder.before.clone(),
+        winders: winder.parent.clone(),
+    });
+    leave.chain(enter).collect()
+}
+
+#[derive(Clone, Copy)]
+/// Expressions evaluating their sub-expressions one after the other
+enum Sequence {
+    Begin,
+    /// Stop at the first false value
+    And,
+    /// Stop at the first true value
+    Or,
+}
+
+/// Step of the evaluation
+enum State {
+    Eval(Rc<Expr>, Environment),
+    Return(Value),
+    /// Call a procedure, along with the position of the call when it comes
+    /// from the source
+    Apply(Value, Vec<Value>, Option<Position>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Builtin procedures that need to access the state of the machine
+pub enum Control {
+    Apply,
+    CallCC,
+    CallEC,
+    DynamicWind,
+    Raise,
+    RaiseContinuable,
+    WithExceptionHandler,
+    Error,
+    Signal,
+    InvokeRestart,
+    ComputeRestarts,
+    /// Establish the condition handlers of `handler-bind`
+    HandlerBind,
+    /// Establish the restarts of `restart-case`
+    WithRestarts,
+}
+
+/// Control procedures along with the names they are bound to
+const CONTROLS: &[(&str, Control)] = &[
+    ("apply", Control::Apply),
+    ("call-with-current-continuation", Control::CallCC),
+    ("call/cc", Control::CallCC),
+    ("call-with-escape-continuation", Control::CallEC),
+    ("call/ec", Control::CallEC),
+    ("dynamic-wind", Control::DynamicWind),
+    ("raise", Control::Raise),
+    ("raise-continuable", Control::RaiseContinuable),
+    ("with-exception-handler", Control::WithExceptionHandler),
+    ("error", Control::Error),
+    ("signal", Control::Signal),
+    ("invoke-restart", Control::InvokeRestart),
+    ("compute-restarts", Control::ComputeRestarts),
+];
+
+impl Control {
+    /// Name used when printing the procedure
+    pub fn name(&self) -> &'static str {
+        match self {
+            Control::Apply => "apply",
+            Control::CallCC => "call/cc",
+            Control::CallEC => "call/ec",
+            Control::DynamicWind => "dynamic-wind",
+            Control::Raise => "raise",
+            Control::RaiseContinuable => "raise-continuable",
+            Control::WithExceptionHandler => "with-exception-handler",
+            Control::Error => "error",
+            Control::Signal => "signal",
+            Control::InvokeRestart => "invoke-restart",
+            Control::ComputeRestarts => "compute-restarts",
+            Control::HandlerBind => "handler-bind",
+            Control::WithRestarts => "restart-case",
+        }
+    }
+}
+
+#[derive(Clone)]
+/// Snapshot of the stack of the machine, of its dynamic extents and of its
+/// dynamic state, which can be reinstated any number of times
+pub struct Continuation {
+    stack: Vec<Frame>,
+    winders: Winders,
+    dynamic: Dynamic,
+}
+
+impl std::fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Continuation({} frames)", self.stack.len())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Continuation that can only unwind the stack up to the call to `call/ec`
+/// that created it, as long as that call has not returned yet
+pub struct Escape {
+    id: u64,
+    /// Index of the `Frame::Escape` in the stack
+    depth: usize,
+}
+
+/// Evaluator keeping its continuation in a heap allocated stack of frames,
+/// so neither tail calls nor deep recursion consume the native stack
+pub struct Machine {
+    stack: Vec<Frame>,
+    /// Maximum number of frames fitting in the memory budget
+    max_frames: usize,
+    /// Maximum nesting of the expressions whose analysis fits in the memory
+    /// budget
+    max_nesting: usize,
+    /// Whether the spare frames are in use, from the time the budget is
+    /// exceeded until the stack fits in it again
+    overflow: bool,
+    /// Id of the next escape continuation
+    next_escape: u64,
+    /// Dynamic extents the evaluation is currently in
+    winders: Winders,
+    dynamic: Dynamic,
+    /// Asked which restart to invoke when an error is not handled
+    debugger: Option<Debugger>,
+    /// Frames the procedures close over, freed once they are garbage
+    frames: Collector,
+}
+
+/// Called with an error nobody handled while some restarts are available,
+/// returning the index of the restart to invoke along with its arguments, if
+/// any
+pub type Debugger = Box<dyn FnMut(&EvalError) -> Option<(usize, Vec<Value>)>>;
+
+impl Machine {
+    /// Create a machine whose stack can grow up to `stack_limit` bytes
+    pub fn new(stack_limit: usize) -> Self {
+        Machine {
+            stack: Vec::new(),
+            max_frames: stack_limit / std::mem::size_of::<Frame>(),
+            max_nesting: (stack_limit / NESTING_SIZE).max(MIN_NESTING),
+            overflow: false,
+            next_escape: 0,
+            winders: None,
+            dynamic: Dynamic::default(),
+            debugger: None,
+            frames: Collector::default(),
+        }
+    }
+
+    /// Analyze the syntax of `expr`, whose source positions are `positions`,
+    /// unless it is nested too deeply for the memory budget
+    pub fn analyze(&self, expr: &SExpression, positions: &Positions) -> Result<Rc<Expr>> {
+        if expr::nesting(expr) > self.max_nesting {
+            return Err(EvalError::at("stack depth exceeded", positions.start()));
+        }
+        expr::analyze(expr, positions)
+    }
+
+    /// Let `debugger` pick a restart when an error is not handled
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Evaluate an expression in the given environment
+    pub fn eval(&mut self, expr: &Rc<Expr>, env: &Environment) -> Result<Value> {
+        let result = self.run(State::Eval(expr.clone(), env.clone()));
+        result.map_err(|e| self.abort(e))
+    }
+
+    /// Leave all the dynamic extents after an error aborted the evaluation,
+    /// running their `after` thunks
+    fn abort(&mut self, mut error: EvalError) -> EvalError {
+        // The frames of the aborted evaluation are left behind
+        self.stack.clear();
+        self.overflow = false;
+        self.dynamic = Dynamic::default();
+        while let Some(winder) = self.winders.take() {
+            self.winders = winder.parent.clone();
+            if let Err(e) = self.run(State::Apply(winder.after.clone(), vec![], None)) {
+                self.stack.clear();
+                error.message = format!("{} (then, while unwinding: {})", error.message, e.message);
+            }
+        }
+        error
+    }
+
+    /// Run the machine until the stack is empty
+    fn run(&mut self, mut state: State) -> Result<Value> {
+        loop {
+            let step = match state {
+                State::Eval(expr, env) => self.eval_step(&expr, env),
+                State::Return(value) => match self.stack.pop() {
+                    Some(frame) => self.return_step(frame, value),
+                    None => return Ok(value),
+                },
+                State::Apply(procedure, args, position) => self
+                    .apply(procedure, args, position)
+                    .map_err(|e| match e.position {
+                        Some(_) => e,
+                        None => EvalError { position, ..e },
+                    }),
+            };
+            state = match step {
+                Ok(state) => state,
+                // Failures can be handled by the program like raised errors
+                Err(e) if self.dynamic.handlers.is_some() => {
+                    let error = Value::Error(Rc::new(ErrorObject {
+                        message: e.message.into(),
+                        irritants: Value::Nil,
+                        position: e.position,
+                    }));
+                    self.raise(error, false)?
+                }
+                // Nothing has been unwound yet, so the restarts can still
+                // be invoked
+                Err(mut e) => {
+                    let restarts = self.restarts();
+                    e.restarts = restarts
+                        .iter()
+                        .map(|(name, restart)| signature(name, restart))
+                        .collect();
+                    let debugger = match &mut self.debugger {
+                        Some(debugger) if !restarts.is_empty() => debugger,
+                        _ => return Err(e),
+                    };
+                    let choice = debugger(&e)
+                        .and_then(|(i, args)| Some((restarts.into_iter().nth(i)?, args)));
+                    match choice {
+                        // Calling the restart with the wrong number of
+                        // arguments would fail while it is still available,
+                        // and ask for a restart again
+                        Some(((name, Value::Closure(closure)), args))
+                            if !closure.lambda.accepts(args.len()) =>
+                        {
+                            return Err(EvalError::new(format!(
+                                "restart {} expects {} arguments, got {}",
+                                name,
+                                closure.lambda.arity(),
+                                args.len()
+                            )))
+                        }
+                        Some(((_, restart), args)) => State::Apply(restart, args, None),
+                        None => {
+                            // The debugger already showed the error and its
+                            // restarts
+                            e.restarts.clear();
+                            e.reported = true;
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Save a frame to resume later, failing when the budget is exhausted.
+    /// The spare frames are then available to the handlers of the error,
+    /// and exhausting them too fails right away.
+    fn push(&mut self, frame: Frame) -> Result<()> {
+        if self.stack.len() < self.max_frames {
+            self.overflow = false;
+        } else if !self.overflow {
+            self.overflow = true;
+            return Err(EvalError::new("stack depth exceeded"));
+        } else if self.stack.len() >= self.max_frames + SPARE_FRAMES {
+            return Err(EvalError::new("stack depth exceeded"));
+        }
+        self.stack.push(frame);
+        Ok(())
+    }
+
+    /// Start the evaluation of an expression
+    fn eval_step(&mut self, expr: &Expr, env: Environment) -> Result<State> {
+        Ok(match expr {
+            Expr::Literal(value) => State::Return(value.clone()),
+            Expr::Variable(name, position) => match env.get(name) {
+                Some(value) => State::Return(value),
+                None => {
+                    return Err(EvalError::at(
+                        format!("unbound symbol `{}`", name),
+                        *position,
+                    ))
+                }
+            },
+            Expr::Define(name, value) => {
+                self.push(Frame::Define(name.clone(), env.clone()))?;
+                State::Eval(value.clone(), env)
+            }
+            Expr::Set(name, value, position) => {
+                self.push(Frame::Set(name.clone(), *position, env.clone()))?;
+                State::Eval(value.clone(), env)
+            }
+            Expr::Application(operator, operands, position) => {
+                self.push(Frame::Operator(operands.clone(), *position, env.clone()))?;
+                State::Eval(operator.clone(), env)
+            }
+            Expr::If(test, then, otherwise) => {
+                self.push(Frame::If(then.clone(), otherwise.clone(), env.clone()))?;
+                State::Eval(test.clone(), env)
+            }
+            Expr::Begin(body) => self.next(Sequence::Begin, body.clone(), 0, env)?,
+            Expr::And(exprs) if exprs.is_empty() => State::Return(Value::Bool(true)),
+            Expr::And(exprs) => self.next(Sequence::And, exprs.clone(), 0, env)?,
+            Expr::Or(exprs) if exprs.is_empty() => State::Return(Value::Bool(false)),
+            Expr::Or(exprs) => self.next(Sequence::Or, exprs.clone(), 0, env)?,
+            Expr::Lambda(lambda) => {
+                self.frames.track(&env);
+                State::Return(Value::Closure(Rc::new(Closure {
+                    lambda: lambda.clone(),
+                    env,
+                })))
+            }
+        })
+    }
+
+    /// Evaluate the expression at index `i` of a sequence. The frame is
+    /// saved only when more expressions follow, so the last one is evaluated
+    /// in tail position.
+    fn next(&mut self, kind: Sequence, exprs: Exprs, i: usize, env: Environment) -> Result<State> {
+        let expr = exprs[i].clone();
+        if i + 1 < exprs.len() {
+            self.push(Frame::Sequence(kind, exprs, i + 1, env.clone()))?;
+        }
+        Ok(State::Eval(expr, env))
+    }
+
+    /// Resume `frame` with the value of the sub-expression it was waiting for
+    fn return_step(&mut self, frame: Frame, value: Value) -> Result<State> {
+        Ok(match frame {
+            Frame::Define(name, env) => {
+                env.define(name.clone(), value);
+                State::Return(Value::Symbol(name))
+            }
+            Frame::Set(name, position, env) => {
+                if !env.set(&name, value.clone()) {
+                    return Err(EvalError::at(
+                        format!("cannot set unbound symbol `{}`", name),
+                        position,
+                    ));
+                }
+                State::Return(value)
+            }
+            Frame::If(then, otherwise, env) => {
+                State::Eval(if value.is_true() { then } else { otherwise }, env)
+            }
+            Frame::Sequence(kind, exprs, i, env) => match kind {
+                Sequence::And if !value.is_true() => State::Return(value),
+                Sequence::Or if value.is_true() => State::Return(value),
+                _ => self.next(kind, exprs, i, env)?,
+            },
+            // The body of `call/ec` returned normally
+            Frame::Escape(..) => State::Return(value),
+            Frame::DynamicWind(before, thunk, after) => {
+                let winder = Rc::new(Winder {
+                    before,
+                    after,
+                    depth: depth(&self.winders),
+                    parent: self.winders.take(),
+                });
+                self.winders = Some(winder.clone());
+                self.push(Frame::Unwind(winder))?;
+                State::Apply(thunk, vec![], None)
+            }
+            Frame::Unwind(winder) => {
+                self.winders = winder.parent.clone();
+                self.push(Frame::Restore(value))?;
+                State::Apply(winder.after.clone(), vec![], None)
+            }
+            Frame::Restore(saved) => State::Return(saved),
+            Frame::Dynamic(dynamic) => {
+                self.dynamic = dynamic;
+                State::Return(value)
+            }
+            Frame::Signal {
+                condition,
+                cluster,
+                index,
+                saved,
+                error,
+            } => self.next_handler(condition, Some(cluster), index, saved, error)?,
+            Frame::Raise(raised) => {
+                return Err(EvalError::new(format!(
+                    "handler returned from non-continuable raise of {}",
+                    raised
+                )))
+            }
+            Frame::Transfer(steps, i, winders, value) => match steps.get(i) {
+                Some(step) => {
+                    self.winders = step.winders.clone();
+                    let thunk = step.thunk.clone();
+                    self.push(Frame::Transfer(steps, i + 1, winders, value))?;
+                    State::Apply(thunk, vec![], None)
+                }
+                None => {
+                    self.winders = winders;
+                    State::Return(value)
+                }
+            },
+            Frame::Operator(operands, position, env) => match operands.first() {
+                None => State::Apply(value, vec![], Some(position)),
+                Some(first) => {
+                    let first = first.clone();
+                    self.push(Frame::Operands {
+                        procedure: value,
+                        args: Vec::with_capacity(operands.len()),
+                        operands,
+                        position,
+                        env: env.clone(),
+                    })?;
+                    State::Eval(first, env)
+                }
+            },
+            Frame::Operands {
+                procedure,
+                mut args,
+                operands,
+                position,
+                env,
+            } => {
+                args.push(value);
+                match operands.get(args.len()) {
+                    None => State::Apply(procedure, args, Some(position)),
+                    Some(next) => {
+                        let next = next.clone();
+                        self.push(Frame::Operands {
+                            procedure,
+                            args,
+                            operands,
+                            position,
+                            env: env.clone(),
+                        })?;
+                        State::Eval(next, env)
+                    }
+                }
+            }
+        })
+    }
+
+    /// Apply the evaluated `procedure` to the already evaluated `args`
+    fn apply(
+        &mut self,
+        procedure: Value,
+        args: Vec<Value>,
+        position: Option<Position>,
+    ) -> Result<State> {
+        match &procedure {
+            Value::Builtin(builtin) => (builtin.func)(&args).map(State::Return),
+            // Enter the body of the closure without saving any frame, which
+            // makes every call in tail position a jump
+            Value::Closure(closure) => {
+                let env = bind_arguments(closure, &args)?;
+                Ok(State::Eval(closure.lambda.body.clone(), env))
+            }
+            Value::Control(control) => self.apply_control(*control, args, position),
+            Value::Continuation(continuation) => {
+                let value = continuation_value(args)?;
+                self.stack = continuation.stack.clone();
+                self.dynamic = continuation.dynamic.clone();
+                self.transfer(&continuation.winders, value)
+            }
+            Value::Escape(escape) => {
+                let value = continuation_value(args)?;
+                match self.stack.get(escape.depth) {
+                    Some(Frame::Escape(id, winders, dynamic)) if *id == escape.id => {
+                        let winders = winders.clone();
+                        self.dynamic = dynamic.clone();
+                        self.stack.truncate(escape.depth);
+                        self.transfer(&winders, value)
+                    }
+                    _ => Err(EvalError::new(
+                        "escape continuation called after its extent ended",
+                    )),
+                }
+            }
+            _ => Err(EvalError::new(format!("{} is not a procedure", procedure))),
+        }
+    }
+
+    /// Apply one of the procedures needing the state of the machine
+    fn apply_control(
+        &mut self,
+        control: Control,
+        mut args: Vec<Value>,
+        position: Option<Position>,
+    ) -> Result<State> {
+        match (control, args.len()) {
+            (Control::Apply, 2..) => {
+                // The last argument is the list of the remaining ones
+                let last = args.pop().expect("Never happen!").to_vec()?;
+                let procedure = args.remove(0);
+                args.extend(last);
+                Ok(State::Apply(procedure, args, position))
+            }
+            (Control::CallCC, 1) => {
+                let continuation = Value::Continuation(Rc::new(Continuation {
+                    stack: self.stack.clone(),
+                    winders: self.winders.clone(),
+                    dynamic: self.dynamic.clone(),
+                }));
+                Ok(State::Apply(args.remove(0), vec![continuation], position))
+            }
+            (Control::CallEC, 1) => {
+                let escape = Escape {
+                    id: self.next_escape,
+                    depth: self.stack.len(),
+                };
+                self.next_escape += 1;
+                self.push(Frame::Escape(
+                    escape.id,
+                    self.winders.clone(),
+                    self.dynamic.clone(),
+                ))?;
+                Ok(State::Apply(
+                    args.remove(0),
+                    vec![Value::Escape(escape)],
+                    position,
+                ))
+            }
+            (Control::DynamicWind, 3) => {
+                let after = args.pop().expect("Never happen!");
+                let thunk = args.pop().expect("Never happen!");
+                let before = args.pop().expect("Never happen!");
+                self.push(Frame::DynamicWind(before.clone(), thunk, after))?;
+                Ok(State::Apply(before, vec![], position))
+            }
+            (Control::Raise, 1) => self.raise(args.remove(0), false),
+            (Control::RaiseContinuable, 1) => self.raise(args.remove(0), true),
+            (Control::WithExceptionHandler, 2) => {
+                let thunk = args.pop().expect("Never happen!");
+                let handler = args.pop().expect("Never happen!");
+                self.push(Frame::Dynamic(self.dynamic.clone()))?;
+                self.dynamic.handlers = Some(Rc::new(Handler {
+                    handler,
+                    parent: self.dynamic.handlers.take(),
+                }));
+                Ok(State::Apply(thunk, vec![], position))
+            }
+            (Control::Error, 1) if matches!(args[0], Value::Condition(_)) => {
+                self.signal(args.remove(0), true)
+            }
+            (Control::Error, 1..) => {
+                let message = match args.remove(0) {
+                    Value::Str(message) => message,
+                    other => {
+                        return Err(EvalError::new(format!(
+                            "`error` expects a string message, got {}",
+                            other
+                        )))
+                    }
+                };
+                let error = Value::Error(Rc::new(ErrorObject {
+                    message,
+                    irritants: Value::list(args),
+                    position,
+                }));
+                self.raise(error, false)
+            }
+            (Control::Signal, 1) => self.signal(args.remove(0), false),
+            (Control::InvokeRestart, 1..) => {
+                let name = args.remove(0);
+                match self.restarts().into_iter().find(|(n, _)| n.eqv(&name)) {
+                    Some((_, restart)) => Ok(State::Apply(restart, args, position)),
+                    None => Err(EvalError::new(format!(
+                        "no restart named {} is active",
+                        name
+                    ))),
+                }
+            }
+            (Control::ComputeRestarts, 0) => Ok(State::Return(Value::list(
+                self.restarts().into_iter().map(|(name, _)| name).collect(),
+            ))),
+            // Called by the analysis with the body and the bindings
+            (Control::HandlerBind | Control::WithRestarts, 1..) if args.len() % 2 == 1 => {
+                let thunk = args.remove(0);
+                let mut bindings = vec![];
+                let mut args = args.into_iter();
+                while let (Some(key), Some(value)) = (args.next(), args.next()) {
+                    if control == Control::HandlerBind && !matches!(key, Value::ConditionType(_)) {
+                        return Err(EvalError::new(format!("{} is not a condition type", key)));
+                    }
+                    bindings.push((key, value));
+                }
+                self.push(Frame::Dynamic(self.dynamic.clone()))?;
+                let clusters = match control {
+                    Control::HandlerBind => &mut self.dynamic.conditions,
+                    _ => &mut self.dynamic.restarts,
+                };
+                *clusters = Some(Rc::new(Cluster {
+                    bindings,
+                    parent: clusters.take(),
+                }));
+                Ok(State::Apply(thunk, vec![], position))
+            }
+            _ => Err(EvalError::new(format!(
+                "`{}` called with {} arguments",
+                control.name(),
+                args.len()
+            ))),
+        }
+    }
+}
+
+/// Machine operations used when applying a continuation, raising an
+/// exception or signalling a condition
+impl Machine {
+    /// Return `value` to the stack in place, after moving from the current
+    /// dynamic extents to `winders`
+    fn transfer(&mut self, winders: &Winders, value: Value) -> Result<State> {
+        let steps = wind_steps(&self.winders, winders);
+        self.push(Frame::Transfer(steps.into(), 0, winders.clone(), value))?;
+        // Start running the steps right away
+        Ok(State::Return(Value::Nil))
+    }
+
+    /// Call the innermost handler with `raised`, with the outer handlers
+    /// installed while it runs. When the raise is continuable the value of
+    /// the handler is returned to the raise, otherwise returning is an error.
+    fn raise(&mut self, raised: Value, continuable: bool) -> Result<State> {
+        let handler = match self.dynamic.handlers.clone() {
+            Some(handler) => handler,
+            None => return Err(uncaught(&raised)),
+        };
+        self.push(Frame::Dynamic(self.dynamic.clone()))?;
+        if !continuable {
+            self.push(Frame::Raise(raised.clone()))?;
+        }
+        self.dynamic.handlers = handler.parent.clone();
+        Ok(State::Apply(handler.handler.clone(), vec![raised], None))
+    }
+
+    /// Call the handlers established for `condition` by `handler-bind`,
+    /// without unwinding the stack
+    fn signal(&mut self, condition: Value, error: bool) -> Result<State> {
+        let condition = match condition {
+            Value::Condition(condition) => condition,
+            other => return Err(EvalError::new(format!("{} is not a condition", other))),
+        };
+        let saved = self.dynamic.clone();
+        let clusters = saved.conditions.clone();
+        self.next_handler(condition, clusters, 0, saved, error)
+    }
+
+    /// Call the next handler for `condition`, starting from the binding at
+    /// `index` of `cluster`. When all the handlers declined, `signal` returns
+    /// `nil` while `error` raises the condition.
+    fn next_handler(
+        &mut self,
+        condition: Rc<Condition>,
+        mut cluster: Clusters,
+        mut index: usize,
+        saved: Dynamic,
+        error: bool,
+    ) -> Result<State> {
+        while let Some(current) = cluster {
+            for (i, (kind, handler)) in current.bindings.iter().enumerate().skip(index) {
+                if !matches!(kind, Value::ConditionType(kind) if condition.kind.is_subtype(kind)) {
+                    continue;
+                }
+                let handler = handler.clone();
+                self.push(Frame::Signal {
+                    condition: condition.clone(),
+                    cluster: current.clone(),
+                    index: i + 1,
+                    saved: saved.clone(),
+                    error,
+                })?;
+                // The handler runs with the handlers established outside of
+                // its own cluster
+                self.dynamic = Dynamic {
+                    conditions: current.parent.clone(),
+                    ..saved
+                };
+                return Ok(State::Apply(
+                    handler,
+                    vec![Value::Condition(condition)],
+                    None,
+                ));
+            }
+            cluster = current.parent.clone();
+            index = 0;
+        }
+        self.dynamic = saved;
+        if error {
+            self.raise(Value::Condition(condition), false)
+        } else {
+            Ok(State::Return(Value::Nil))
+        }
+    }
+
+    /// Restarts in effect along with their names, innermost first
+    fn restarts(&self) -> Vec<(Value, Value)> {
+        let mut restarts = vec![];
+        let mut cluster = &self.dynamic.restarts;
+        while let Some(current) = cluster {
+            restarts.extend(current.bindings.iter().cloned());
+            cluster = &current.parent;
+        }
+        restarts
+    }
+}
+
+/// Error reported when no handler is installed for a raised value
+fn uncaught(raised: &Value) -> EvalError {
+    match raised {
+        Value::Error(error) => EvalError {
+            position: error.position,
+            ..EvalError::new(error.to_string())
+        },
+        Value::Condition(condition) => EvalError::new(format!("unhandled condition {}", condition)),
+        _ => EvalError::new(format!("uncaught exception: {}", raised)),
+    }
+}
+
+/// Name of a restart followed by its parameters, as `use-value (x)`
+fn signature(name: &Value, restart: &Value) -> Rc<str> {
+    match restart {
+        Value::Closure(closure) => format!("{} {}", name, closure.lambda.signature()).into(),
+        _ => name.to_string().into(),
+    }
+}
+
+/// Value passed to a continuation, which accepts zero or one argument
+fn continuation_value(mut args: Vec<Value>) -> Result<Value> {
+    match args.len() {
+        0 => Ok(Value::Nil),
+        1 => Ok(args.remove(0)),
+        n => Err(EvalError::new(format!(
+            "continuations expect at most 1 argument, got {}",
+            n
+        ))),
+    }
+}
+
+/// Create the frame of a call to `closure`, binding its parameters to `args`
+fn bind_arguments(closure: &Closure, args: &[Value]) -> Result<Environment> {
+    let lambda = &closure.lambda;
+    if !lambda.accepts(args.len()) {
+        return Err(EvalError::new(format!(
+            "`{}` expects {} arguments, got {}",
+            lambda.name.as_deref().unwrap_or("lambda"),
+            lambda.arity(),
+            args.len()
+        )));
+    }
+    let env = closure.env.extend();
+    for (param, arg) in lambda.params.iter().zip(args) {
+        env.define(param.clone(), arg.clone());
+    }
+    if let Some(rest) = &lambda.rest {
+        env.define(
+            rest.clone(),
+            Value::list(args[lambda.params.len()..].to_vec()),
+        );
+    }
+    Ok(env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Evaluate the expressions of `program` one after the other on a
+    /// machine whose stack is limited to `stack_limit` bytes, returning the
+    /// value of the last one
+    fn run(program: &str, stack_limit: usize) -> Result<Value> {
+        run_on(&mut Machine::new(stack_limit), program)
+    }
+
+    /// Evaluate the expressions of `program` one after the other on
+    /// `machine`, returning the value of the last one
+    fn run_on(machine: &mut Machine, program: &str) -> Result<Value> {
+        run_in(machine, &global_environment(), program)
+    }
+
+    /// Evaluate the expressions of `program` one after the other on
+    /// `machine` in `env`, which is kept for the next programs
+    fn run_in(machine: &mut Machine, env: &Environment, program: &str) -> Result<Value> {
+        let mut last = Value::Nil;
+        for parsed in Parser::init(Lexer::new(program.as_bytes())) {
+            let (expr, positions) = parsed.unwrap_or_else(|e| panic!("{}", e));
+            last = machine.eval(&machine.analyze(&expr, &positions)?, env)?;
+        }
+        Ok(last)
+    }
+
+    /// Evaluate `program`, returning the value of its last expression as
+    /// written
+    fn eval(program: &str) -> String {
+        match run(program, DEFAULT_STACK_LIMIT) {
+            Ok(value) => value.to_string(),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Loop a million times through the procedure `count` given by
+    /// `definitions`, which calls itself in tail position, with room for a
+    /// few frames only
+    fn count(definitions: &str) -> String {
+        let program = format!("(define n 1000000) {} (count 0)", definitions);
+        match run(&program, 64 * std::mem::size_of::<Frame>()) {
+            Ok(value) => value.to_string(),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn tail_call_in_if() {
+        let result = count("(define (count i) (if (== i n) i (count (+ i 1))))");
+        assert_eq!(result, "1000000");
+    }
+
+    #[test]
+    fn tail_call_in_cond() {
+        let result = count("(define (count i) (cond ((< i n) (count (+ i 1))) (else i)))");
+        assert_eq!(result, "1000000");
+    }
+
+    #[test]
+    fn tail_call_in_begin() {
+        let result = count(
+            "(define (count i)
+               (begin (+ i 1) (if (< i n) (count (+ i 1)) i)))",
+        );
+        assert_eq!(result, "1000000");
+    }
+
+    #[test]
+    fn tail_call_in_let() {
+        let result = count(
+            "(define (count i)
+               (let ((next (+ i 1))) (if (> next n) i (count next))))",
+        );
+        assert_eq!(result, "1000000");
+    }
+
+    #[test]
+    fn tail_call_in_and_or() {
+        let result = count("(define (count i) (or (and (== i n) i) (count (+ i 1))))");
+        assert_eq!(result, "1000000");
+    }
+
+    #[test]
+    fn mutual_tail_calls() {
+        let result = count(
+            "(define (count i) (if (== i n) i (other (+ i 1))))
+             (define (other i) (if (== i n) i (count (+ i 1))))",
+        );
+        assert_eq!(result, "1000000");
+    }
+
+    #[test]
+    fn tail_call_through_apply() {
+        let result = count("(define (count i) (if (== i n) i (apply count (list (+ i 1)))))");
+        assert_eq!(result, "1000000");
+    }
+
+    /// Program recursing without end outside of tail position
+    const RECURSE: &str = "(define (f n) (+ 1 (f n)))";
+
+    /// Budget of the stack in the tests exceeding it
+    const SMALL_STACK: usize = 1000 * std::mem::size_of::<Frame>();
+
+    #[test]
+    fn stack_overflow_is_an_error() {
+        let error = run(&format!("{} (f 1)", RECURSE), SMALL_STACK).unwrap_err();
+        assert_eq!(error.message, "stack depth exceeded");
+    }
+
+    #[test]
+    fn stack_overflow_can_be_caught() {
+        let program = format!(
+            "(guard (e ((error-object? e) (error-object-message e))) {} (f 1))",
+            RECURSE
+        );
+        let caught = run(&program, SMALL_STACK).unwrap();
+        assert_eq!(caught.to_string(), "\"stack depth exceeded\"");
+        // The whole budget is available again once the handler is done
+        let program = format!("{} (guard (e (#t 'caught)) (f 1))", program);
+        assert_eq!(run(&program, SMALL_STACK).unwrap().to_string(), "caught");
+    }
+
+    #[test]
+    fn stack_overflow_in_handler_is_an_error() {
+        let program = format!(
+            "{} (with-exception-handler (lambda (e) (f 1)) (lambda () (f 1)))",
+            RECURSE
+        );
+        let error = run(&program, SMALL_STACK).unwrap_err();
+        assert_eq!(error.message, "stack depth exceeded");
+    }
+
+    #[test]
+    fn code_nesting_is_limited_by_the_budget() {
+        let nested = |depth| format!("{}1{}", "(+ 1 ".repeat(depth), ")".repeat(depth));
+        let budget = 100 * NESTING_SIZE;
+        assert_eq!(run(&nested(100), budget).unwrap().to_string(), "101");
+        let error = run(&nested(101), budget).unwrap_err();
+        assert_eq!(error.message, "stack depth exceeded");
+        assert_eq!(error.position, Some(Position { line: 1, column: 1 }));
+    }
+
+    #[test]
+    fn data_nesting_is_not_limited() {
+        // Quoted data and constant templates are converted without recursing
+        let depth = 100_000;
+        let data = format!("{}x{}", "(a ".repeat(depth), ")".repeat(depth));
+        for program in [format!("'{}", data), format!("`{}", data)] {
+            assert_eq!(run(&program, SMALL_STACK).unwrap().to_string(), data);
+        }
+    }
+
+    #[test]
+    fn continuation_reentry() {
+        // Jump back into a computation that already returned, until the
+        // counter reaches 3
+        let result = eval(
+            "(begin
+               (define saved nil)
+               (define count 0)
+               (define result (+ 100 (call/cc (lambda (k) (set! saved k) 0))))
+               (set! count (+ count 1))
+               (if (< count 3) (saved count) nil)
+               (list result count))",
+        );
+        assert_eq!(result, "(102 3)");
+    }
+
+    #[test]
+    fn generator() {
+        // Walk a tree lazily, suspending the walk after each leaf
+        let result = eval(
+            "(define (make-generator tree)
+               (define return nil)
+               (define (walk tree)
+                 (cond ((null? tree) nil)
+                       ((pair? tree) (walk (car tree)) (walk (cdr tree)))
+                       (else (call/cc
+                              (lambda (resume)
+                                (set! next (lambda () (resume nil)))
+                                (return tree))))))
+               (define (next)
+                 (walk tree)
+                 (return 'done))
+               (lambda ()
+                 (call/cc (lambda (k) (set! return k) (next)))))
+             (define gen (make-generator '((1 2) (3 (4)))))
+             (list (gen) (gen) (gen) (gen) (gen) (gen))",
+        );
+        assert_eq!(result, "(1 2 3 4 done done)");
+    }
+
+    #[test]
+    fn early_exit() {
+        let program = "
+            (define (find-pair xs ys target)
+              (call/ec
+               (lambda (return)
+                 (let outer ((a xs))
+                   (unless (null? a)
+                     (let inner ((b ys))
+                       (unless (null? b)
+                         (when (== (+ (car a) (car b)) target)
+                           (return (list (car a) (car b))))
+                         (inner (cdr b))))
+                     (outer (cdr a))))
+                 nil)))";
+        let found = eval(&format!("{} (find-pair '(1 2 3) '(5 6 7) 10)", program));
+        assert_eq!(found, "(3 7)");
+        let missing = eval(&format!("{} (find-pair '(1 2 3) '(5 6 7) 20)", program));
+        assert_eq!(missing, "()");
+        // Leaving with call/cc works the same
+        let program = program.replace("call/ec", "call/cc");
+        let found = eval(&format!("{} (find-pair '(1 2 3) '(5 6 7) 10)", program));
+        assert_eq!(found, "(3 7)");
+    }
+
+    #[test]
+    fn apply_continuation() {
+        let result = eval("(call/cc (lambda (k) (apply k (list (+ 1 2 3 4)))))");
+        assert_eq!(result, "10");
+    }
+
+    #[test]
+    fn escape_after_extent() {
+        let program = "(define k (call/ec (lambda (k) k))) (k 1)";
+        let error = run(program, DEFAULT_STACK_LIMIT).unwrap_err();
+        assert_eq!(
+            error.message,
+            "escape continuation called after its extent ended"
+        );
+    }
+
+    /// Evaluate `program` with a debugger invoking the restart at `index`
+    /// with `args`, returning the result along with the number of times the
+    /// debugger was asked
+    fn debug(program: &str, index: usize, args: Vec<Value>) -> (Result<Value>, usize) {
+        let asked = Rc::new(std::cell::Cell::new(0));
+        let mut machine = Machine::new(DEFAULT_STACK_LIMIT);
+        let counter = asked.clone();
+        machine.set_debugger(Box::new(move |_| {
+            counter.set(counter.get() + 1);
+            Some((index, args.clone()))
+        }));
+        let result = run_on(&mut machine, program);
+        (result, asked.get())
+    }
+
+    /// Definitions recording the order in which things happen in `trace`
+    const TRACE: &str = "(define trace '()) (define (note x) (set! trace (append trace (list x))))";
+
+    #[test]
+    fn dynamic_wind_escape() {
+        let program = "
+            (define result
+              (call/ec
+               (lambda (k)
+                 (dynamic-wind
+                  (lambda () (note 'in))
+                  (lambda () (k 'escaped) (note 'unreachable))
+                  (lambda () (note 'out))))))
+            (list result trace)";
+        assert_eq!(
+            eval(&format!("{} {}", TRACE, program)),
+            "(escaped (in out))"
+        );
+    }
+
+    #[test]
+    fn dynamic_wind_nested_escape() {
+        // The inner extent is left first
+        let program = "
+            (call/ec
+             (lambda (k)
+               (dynamic-wind
+                (lambda () (note 'outer-in))
+                (lambda ()
+                  (dynamic-wind
+                   (lambda () (note 'inner-in))
+                   (lambda () (k 0))
+                   (lambda () (note 'inner-out))))
+                (lambda () (note 'outer-out)))))
+            trace";
+        assert_eq!(
+            eval(&format!("{} {}", TRACE, program)),
+            "(outer-in inner-in inner-out outer-out)"
+        );
+    }
+
+    #[test]
+    fn dynamic_wind_reentry() {
+        // Jumping back into the extent runs the before thunk again
+        let program = "
+            (begin
+              (define again '())
+              (define count 0)
+              (dynamic-wind
+               (lambda () (note 'enter))
+               (lambda () (call/cc (lambda (k) (set! again k))))
+               (lambda () (note 'leave)))
+              (set! count (+ count 1))
+              (when (< count 3) (again '()))
+              trace)";
+        assert_eq!(
+            eval(&format!("{} {}", TRACE, program)),
+            "(enter leave enter leave enter leave)"
+        );
+    }
+
+    #[test]
+    fn unwind_protect_cleanup() {
+        let program = "
+            (list (unwind-protect 1 (note 'returned))
+                  (call/ec (lambda (k) (unwind-protect (k 'escaped) (note 'cleanup))))
+                  trace)";
+        assert_eq!(
+            eval(&format!("{} {}", TRACE, program)),
+            "(1 escaped (returned cleanup))"
+        );
+    }
+
+    #[test]
+    fn after_thunks_run_when_an_error_aborts() {
+        let mut machine = Machine::new(DEFAULT_STACK_LIMIT);
+        let env = global_environment();
+        run_in(&mut machine, &env, TRACE).unwrap();
+        let program = "
+            (dynamic-wind
+             (lambda () (note 'in))
+             (lambda () (unwind-protect (car 1) (note 'cleanup)))
+             (lambda () (note 'out)))";
+        let error = run_in(&mut machine, &env, program).unwrap_err();
+        assert_eq!(error.message, "`car` expects a pair, got 1");
+        let trace = run_in(&mut machine, &env, "trace").unwrap();
+        assert_eq!(trace.to_string(), "(in cleanup out)");
+        // The guard clauses run once the extent is left
+        let program = "
+            (guard (e (#t trace))
+              (dynamic-wind (lambda () (note 'again)) (lambda () (car 1)) (lambda () (note 'left))))";
+        let trace = run_in(&mut machine, &env, program).unwrap();
+        assert_eq!(trace.to_string(), "(in cleanup out again left)");
+    }
+
+    #[test]
+    fn guard_reraises_to_the_outer_guard() {
+        let program = "
+            (guard (outer (#t (list 'outer outer)))
+              (guard (inner ((== inner 0) 'zero))
+                (raise 7)))";
+        assert_eq!(eval(program), "(outer 7)");
+        let program = "
+            (guard (outer (#t (list 'outer outer)))
+              (guard (inner ((== inner 0) 'zero))
+                (raise 0)))";
+        assert_eq!(eval(program), "zero");
+    }
+
+    #[test]
+    fn handler_returning_from_raise_is_an_error() {
+        let program = "(with-exception-handler (lambda (e) 0) (lambda () (raise 'oops)))";
+        let error = run(program, DEFAULT_STACK_LIMIT).unwrap_err();
+        assert_eq!(
+            error.message,
+            "handler returned from non-continuable raise of oops"
+        );
+    }
+
+    #[test]
+    fn raise_continuable_returns_the_handler_value() {
+        let program = "
+            (with-exception-handler
+              (lambda (e) (* e 10))
+              (lambda () (+ 1 (raise-continuable 4))))";
+        assert_eq!(eval(program), "41");
+    }
+
+    #[test]
+    fn error_object_fields() {
+        let program = "
+            (guard (e (#t (list (error-object-message e)
+                                (error-object-irritants e)
+                                (error-object-location e))))
+              (error \"negative number\" -5 'x))";
+        assert_eq!(eval(program), "(\"negative number\" (-5 x) (5 15))");
+        let program = "(guard (e ((error-object? e) (error-object-message e))) (/ 1 0))";
+        assert_eq!(eval(program), "\"division by zero\"");
+    }
+
+    #[test]
+    fn internal_definitions_are_freed() {
+        // Each call makes a cycle between its frame and the procedure defined
+        // in it
+        let mut machine = Machine::new(DEFAULT_STACK_LIMIT);
+        let program = "
+            (define (f) (define (g) 1) (g))
+            (define (repeat n) (when (> n 0) (f) (repeat (- n 1))))
+            (repeat 100000)";
+        run_on(&mut machine, program).unwrap();
+        assert!(
+            machine.frames.len() < 30_000,
+            "{} frames",
+            machine.frames.len()
+        );
+    }
+
+    #[test]
+    fn named_let_and_letrec_scopes_are_freed() {
+        // The loop procedure of a named let, and the procedures of letrec,
+        // are defined in the scope they close over
+        let mut machine = Machine::new(DEFAULT_STACK_LIMIT);
+        let program = "
+            (define (h) (let loop ((i 0)) (if (== i 1) i (loop (+ i 1)))))
+            (define (r) (letrec ((f (lambda () 1))) (f)))
+            (define (repeat n) (when (> n 0) (h) (r) (repeat (- n 1))))
+            (repeat 50000)";
+        run_on(&mut machine, program).unwrap();
+        assert!(
+            machine.frames.len() < 30_000,
+            "{} frames",
+            machine.frames.len()
+        );
+    }
+
+    #[test]
+    fn live_frames_survive_collections() {
+        // The frames of the counters are reachable from a global, from the
+        // stack and from a list in a frame
+        let program = "
+            (define (make-counter)
+              (define n 0)
+              (define (next) (set! n (+ n 1)) n)
+              next)
+            (define (churn k) (when (> k 0) (make-counter) (churn (- k 1))))
+            (define global (make-counter))
+            (global)
+            (define (keep)
+              (let ((counters (list (make-counter) (make-counter))))
+                ((car counters))
+                (churn 30000)
+                (list ((car counters)) ((car (cdr counters))))))
+            (list (keep) (global) (global))";
+        assert_eq!(eval(program), "((2 1) 2 3)");
+    }
+
+    /// Message of the error that evaluating `program` fails with
+    fn failure(program: &str) -> String {
+        match run(program, DEFAULT_STACK_LIMIT) {
+            Ok(value) => panic!("{} evaluated to {}", program, value),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn if_begin_and_or() {
+        assert_eq!(eval("(if '() 1 2)"), "2");
+        assert_eq!(eval("(if 0 1 2)"), "1");
+        assert_eq!(eval("(if #f 1)"), "()");
+        assert_eq!(eval("(begin (define x 1) (set! x (+ x 1)) x)"), "2");
+        assert_eq!(eval("(begin)"), "()");
+        // The operands after the deciding one are not evaluated
+        assert_eq!(eval("(and 1 #f (car 1))"), "#f");
+        assert_eq!(eval("(and 1 2)"), "2");
+        assert_eq!(eval("(and)"), "#t");
+        assert_eq!(eval("(or #f 2 (car 1))"), "2");
+        assert_eq!(eval("(or)"), "#f");
+        assert_eq!(failure("(if)"), "Evaluation Error: 1:1 malformed if: (if)");
+        assert_eq!(
+            failure("(if 1 2 3 4)"),
+            "Evaluation Error: 1:1 malformed if: (if 1 2 3 4)"
+        );
+    }
+
+    #[test]
+    fn cond_clauses() {
+        assert_eq!(eval("(cond (#f 1) ((== 1 1) 2 3) (else 4))"), "3");
+        assert_eq!(eval("(cond (#f 1) (else 4))"), "4");
+        assert_eq!(eval("(cond (#f 1))"), "()");
+        // A clause without body gives the value of its test
+        assert_eq!(eval("(cond (#f) (7))"), "7");
+        // The arrow passes the value of the test to the receiver
+        assert_eq!(
+            eval("(cond ((car '(5)) => (lambda (x) (* x 2))) (else 0))"),
+            "10"
+        );
+        assert_eq!(eval("(cond (#f => car) (else 0))"), "0");
+        assert_eq!(
+            failure("(cond (else 1) (#t 2))"),
+            "Evaluation Error: 1:1 malformed cond: (cond (else 1) (#t 2))"
+        );
+        assert_eq!(
+            failure("(cond 1)"),
+            "Evaluation Error: 1:1 malformed cond: (cond 1)"
+        );
+    }
+
+    #[test]
+    fn case_clauses() {
+        let program = "(define (kind x) (case x ((1 2 3) 'small) ((a b) 'letter) (else 'other)))";
+        assert_eq!(
+            eval(&format!("{} (list (kind 2) (kind 'b) (kind 9))", program)),
+            "(small letter other)"
+        );
+        assert_eq!(eval("(case 4 ((1) 'one))"), "()");
+        // The key is evaluated once
+        assert_eq!(
+            eval("(define n 0) (case (begin (set! n (+ n 1)) n) ((0) 'zero) ((1) 'one)) n"),
+            "1"
+        );
+        assert_eq!(
+            failure("(case 1 (1 'one))"),
+            "Evaluation Error: 1:1 malformed case: (case 1 (1 'one))"
+        );
+        assert_eq!(
+            failure("(case)"),
+            "Evaluation Error: 1:1 malformed case: (case)"
+        );
+    }
+
+    #[test]
+    fn when_and_unless() {
+        assert_eq!(eval("(when (== 1 1) 'a 'b)"), "b");
+        assert_eq!(eval("(when #f (car 1))"), "()");
+        assert_eq!(eval("(unless #f 'a 'b)"), "b");
+        assert_eq!(eval("(unless 1 (car 1))"), "()");
+        assert_eq!(
+            failure("(when #t)"),
+            "Evaluation Error: 1:1 malformed when: (when #t)"
+        );
+        assert_eq!(
+            failure("(unless)"),
+            "Evaluation Error: 1:1 malformed unless: (unless)"
+        );
+    }
+
+    #[test]
+    fn let_forms() {
+        // The values of let are evaluated outside of the new scope, the ones
+        // of let* in the scope of the previous bindings
+        assert_eq!(eval("(define x 1) (let ((x 2) (y x)) (list x y))"), "(2 1)");
+        assert_eq!(
+            eval("(define x 1) (let* ((x 2) (y x)) (list x y))"),
+            "(2 2)"
+        );
+        assert_eq!(eval("(let* () 5)"), "5");
+        assert_eq!(
+            eval("(let loop ((i 0) (acc '())) (if (== i 3) acc (loop (+ i 1) (cons i acc))))"),
+            "(2 1 0)"
+        );
+        assert_eq!(
+            failure("(let ((x)) x)"),
+            "Evaluation Error: 1:1 malformed let: (let ((x)) x)"
+        );
+        assert_eq!(
+            failure("(let ((x 1) (x 2)) x)"),
+            "Evaluation Error: 1:1 malformed let: (let ((x 1) (x 2)) x)"
+        );
+        assert_eq!(
+            failure("(let* (x) x)"),
+            "Evaluation Error: 1:1 malformed let*: (let* (x) x)"
+        );
+    }
+
+    #[test]
+    fn letrec_forms() {
+        let program = "
+            (letrec ((even? (lambda (n) (if (== n 0) #t (odd? (- n 1)))))
+                     (odd? (lambda (n) (if (== n 0) #f (even? (- n 1))))))
+              (list (even? 10) (odd? 7) (even? 3)))";
+        assert_eq!(eval(program), "(#t #t #f)");
+        assert_eq!(eval("(letrec* ((a 1) (b (+ a 1))) (list a b))"), "(1 2)");
+        assert_eq!(
+            failure("(letrec ((f)) f)"),
+            "Evaluation Error: 1:1 malformed letrec: (letrec ((f)) f)"
+        );
+        assert_eq!(
+            failure("(letrec* ((a 1)))"),
+            "Evaluation Error: 1:1 malformed letrec*: (letrec* ((a 1)))"
+        );
+    }
+
+    #[test]
+    fn debugger_invokes_restart_with_arguments() {
+        let program = "(+ 1 (restart-case (error \"bad\") (skip () 0) (use-value (x) x)))";
+        let (result, asked) = debug(program, 1, vec![Value::Integer(41)]);
+        assert_eq!(result.unwrap().to_string(), "42");
+        assert_eq!(asked, 1);
+        let (result, _) = debug(program, 0, vec![]);
+        assert_eq!(result.unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn debugger_rejects_wrong_arguments() {
+        let program = "(restart-case (error \"bad\") (use-value (x) x))";
+        let (result, asked) = debug(program, 0, vec![]);
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.message,
+            "restart use-value expects 1 arguments, got 0"
+        );
+        assert!(error.restarts.is_empty());
+        assert_eq!(asked, 1);
+    }
+
+    #[test]
+    fn debugger_abort_is_reported_once() {
+        let program = "(restart-case (error \"bad\") (skip () 0))";
+        let (result, asked) = debug(program, 5, vec![]);
+        let error = result.unwrap_err();
+        assert_eq!(error.message, "bad");
+        assert!(error.reported);
+        assert_eq!(asked, 1);
+        let (result, _) = debug(program, 0, vec![Value::Nil]);
+        assert!(!result.unwrap_err().reported);
+    }
+
+    #[test]
+    fn quasiquote_vector() {
+        assert_eq!(eval("`#(1 ,(+ 1 1) ,@(list 3 4))"), "#(1 2 3 4)");
+        assert_eq!(eval("`(a #(b ,(+ 1 2)) c)"), "(a #(b 3) c)");
+        assert_eq!(eval("`#(a `#(,(b ,(+ 1 2))))"), "#(a `#(,(b 3)))");
+        assert_eq!(eval("`#(a ,'b)"), "#(a b)");
+    }
+
+    #[test]
+    fn dotted_pairs() {
+        assert_eq!(eval("(cdr '(a . b))"), "b");
+        assert_eq!(eval("'(a b . (c d))"), "(a b c d)");
+        assert_eq!(eval("'(a . (b . c))"), "(a b . c)");
+        assert_eq!(
+            eval("((lambda (a . rest) (list a rest)) 1 2 3)"),
+            "(1 (2 3))"
+        );
+        assert_eq!(eval("(define (f . args) args) (f 1 2)"), "(1 2)");
+        assert_eq!(eval("`(1 . ,(+ 1 1))"), "(1 . 2)");
+        assert_eq!(eval("`(,(+ 1 1) . b)"), "(2 . b)");
+        assert_eq!(eval("`(1 ,@(list 2 3) . ,(+ 2 2))"), "(1 2 3 . 4)");
+        assert_eq!(
+            eval("`(1 . `(2 . ,(3 . ,(+ 2 2))))"),
+            "(1 quasiquote (2 unquote (3 . 4)))"
+        );
+        assert!(run("(+ 1 . 2)", DEFAULT_STACK_LIMIT).is_err());
+        assert!(run("(lambda (a . 1) a)", DEFAULT_STACK_LIMIT).is_err());
+    }
+}
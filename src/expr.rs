@@ -0,0 +1,883 @@
+use std::rc::Rc;
+
+use crate::builtins;
+use crate::eval::{Control, EvalError, Result};
+use crate::lexer::Position;
+use crate::parser::{Positions, SExpression};
+use crate::value::{Builtin, Value};
+
+#[derive(Debug)]
+/// Expression produced by the syntactic analysis, ready to be evaluated
+pub enum Expr {
+    /// Self evaluating or quoted datum
+    Literal(Value),
+    /// Reference to a variable, along with the position of its symbol
+    Variable(Rc<str>, Position),
+    Define(Rc<str>, Rc<Expr>),
+    Set(Rc<str>, Rc<Expr>, Position),
+    /// Procedure call, along with the position of its opening paren
+    Application(Rc<Expr>, Exprs, Position),
+    Lambda(Rc<Lambda>),
+    If(Rc<Expr>, Rc<Expr>, Rc<Expr>),
+    /// Sequence of expressions, evaluating to the last one
+    Begin(Exprs),
+    And(Exprs),
+    Or(Exprs),
+}
+
+/// Shared list of sub-expressions
+pub type Exprs = Rc<[Rc<Expr>]>;
+
+#[derive(Debug)]
+/// Code of a procedure, turned into a closure when evaluated
+pub struct Lambda {
+    /// Name given by the `define` shorthand, only used when printing
+    pub name: Option<Rc<str>>,
+    pub params: Vec<Rc<str>>,
+    /// Parameter collecting the extra arguments into a list, if any
+    pub rest: Option<Rc<str>>,
+    pub body: Rc<Expr>,
+}
+
+impl Lambda {
+    /// Describe the number of arguments accepted by the procedure
+    pub fn arity(&self) -> String {
+        match self.rest {
+            Some(_) => format!("at least {}", self.params.len()),
+            None => self.params.len().to_string(),
+        }
+    }
+
+    /// Check whether the procedure can be called with `n` arguments
+    pub fn accepts(&self, n: usize) -> bool {
+        match self.rest {
+            Some(_) => n >= self.params.len(),
+            None => n == self.params.len(),
+        }
+    }
+
+    /// Describe the parameters as they are written, as `(x y . rest)`
+    pub fn signature(&self) -> String {
+        let params = self.params.join(" ");
+        match &self.rest {
+            Some(rest) if params.is_empty() => rest.to_string(),
+            Some(rest) => format!("({} . {})", params, rest),
+            None => format!("({})", params),
+        }
+    }
+}
+
+/// Sub-expression along with its source positions
+type Item<'a> = (&'a SExpression, &'a Positions);
+
+/// Required parameters and the optional rest one of a lambda
+type Parameters = (Vec<Rc<str>>, Option<Rc<str>>);
+
+/// Special form under analysis, used to report malformed syntax
+struct Form<'a> {
+    name: &'a str,
+    expr: &'a SExpression,
+    start: Position,
+}
+
+impl Form<'_> {
+    /// Error pointing to the whole list of the form
+    fn malformed(&self) -> EvalError {
+        EvalError::at(
+            format!("malformed {}: {}", self.name, self.expr),
+            self.start,
+        )
+    }
+}
+
+/// Names of the variables introduced by the analysis. They contain a space,
+/// which the lexer never puts in a symbol, so user code cannot refer to them.
+const CASE_KEY: &str = " case-key";
+const COND_TEST: &str = " cond-test";
+const GUARD_EXIT: &str = " guard-exit";
+const RESTART_EXIT: &str = " restart-exit";
+const DEFERRED_VALUE: &str = " deferred-value";
+
+/// Analyze the syntax of `expr`, whose source positions are `positions`
+pub fn analyze(expr: &SExpression, positions: &Positions) -> Result<Rc<Expr>> {
+    let items = match elements(expr, positions) {
+        Some(items) => items,
+        None => {
+            return Ok(Rc::new(match expr {
+                SExpression::DottedList(..) => {
+                    return Err(EvalError::at(
+                        format!("dotted list in expression: {}", expr),
+                        positions.start(),
+                    ))
+                }
+                SExpression::Symbol(name) => {
+                    Expr::Variable(name.as_str().into(), positions.start())
+                }
+                _ => Expr::Literal(Value::from(expr)),
+            }))
+        }
+    };
+    let start = positions.start();
+    let (op, args) = match items.as_slice() {
+        // The empty list evaluates to itself, that is `nil`
+        [] => return Ok(Rc::new(Expr::Literal(Value::Nil))),
+        [(SExpression::Symbol(op), _), args @ ..] => (op.as_str(), args),
+        _ => return analyze_application(&items, start),
+    };
+    let form = Form {
+        name: op,
+        expr,
+        start,
+    };
+    match op {
+        "quote" => match args {
+            [(datum, _)] => Ok(Rc::new(Expr::Literal(Value::from(*datum)))),
+            _ => Err(form.malformed()),
+        },
+        "quasiquote" => match args {
+            [(template, pos)] => analyze_quasiquote(template, pos, 1),
+            _ => Err(form.malformed()),
+        },
+        "unquote" | "unquote-splicing" => Err(EvalError::at(
+            format!("{} outside of quasiquote: {}", op, expr),
+            start,
+        )),
+        "define" => analyze_define(&form, args),
+        "set!" => match args {
+            [(SExpression::Symbol(name), name_pos), (value, pos)] => Ok(Rc::new(Expr::Set(
+                name.as_str().into(),
+                analyze(value, pos)?,
+                name_pos.start(),
+            ))),
+            _ => Err(form.malformed()),
+        },
+        "lambda" => match args {
+            [(params, _), body @ ..] => analyze_lambda(&form, None, params, body),
+            _ => Err(form.malformed()),
+        },
+        "if" => match args {
+            [(test, test_pos), (then, then_pos), otherwise @ ..] => {
+                let otherwise = match otherwise {
+                    [] => Rc::new(Expr::Literal(Value::Nil)),
+                    [(otherwise, pos)] => analyze(otherwise, pos)?,
+                    _ => return Err(form.malformed()),
+                };
+                Ok(Rc::new(Expr::If(
+                    analyze(test, test_pos)?,
+                    analyze(then, then_pos)?,
+                    otherwise,
+                )))
+            }
+            _ => Err(form.malformed()),
+        },
+        "begin" => Ok(sequence(analyze_all(args)?)),
+        "and" => Ok(Rc::new(Expr::And(analyze_all(args)?.into()))),
+        "or" => Ok(Rc::new(Expr::Or(analyze_all(args)?.into()))),
+        "when" | "unless" => match args {
+            [(test, pos), body @ ..] if !body.is_empty() => {
+                let test = analyze(test, pos)?;
+                let body = sequence(analyze_all(body)?);
+                let nothing = Rc::new(Expr::Literal(Value::Nil));
+                Ok(Rc::new(if op == "when" {
+                    Expr::If(test, body, nothing)
+                } else {
+                    Expr::If(test, nothing, body)
+                }))
+            }
+            _ => Err(form.malformed()),
+        },
+        "cond" => analyze_cond(&form, args, Rc::new(Expr::Literal(Value::Nil))),
+        "case" => analyze_case(&form, args),
+        "let" => analyze_let(&form, args),
+        "let*" => analyze_let_star(&form, args),
+        "letrec" | "letrec*" => analyze_letrec(&form, args),
+        "unwind-protect" => analyze_unwind_protect(&form, args),
+        "guard" => analyze_guard(&form, args),
+        "define-condition" => analyze_define_condition(&form, args),
+        "handler-bind" => analyze_handler_bind(&form, args),
+        "restart-case" => analyze_restart_case(&form, args),
+        _ => analyze_application(&items, start),
+    }
+}
+
+/// Depth of the lists `analyze` recurses into to analyze `expr`. Quoted data
+/// and the quasiquote templates without unquotes are converted into values
+/// without recursing, so their lists do not count.
+pub fn nesting(expr: &SExpression) -> usize {
+    let mut deepest = 0;
+    let mut pending = vec![(expr, 0)];
+    while let Some((expr, depth)) = pending.pop() {
+        let (items, tail) = match expr {
+            SExpression::List(items) | SExpression::Vector(items) => (items, None),
+            SExpression::DottedList(items, tail) => (items, Some(tail.as_ref())),
+            _ => continue,
+        };
+        deepest = deepest.max(depth + 1);
+        let constant = match items.as_slice() {
+            [op, _] if is_symbol(op, "quote") => true,
+            [op, template] => is_symbol(op, "quasiquote") && !unquotes(template, 1),
+            _ => false,
+        };
+        if !constant {
+            pending.extend(items.iter().chain(tail).map(|item| (item, depth + 1)));
+        }
+    }
+    deepest
+}
+
+/// Pair the elements of a list with their positions, `None` for atoms
+fn elements<'a>(expr: &'a SExpression, positions: &'a Positions) -> Option<Vec<Item<'a>>> {
+    match (expr, positions) {
+        (SExpression::List(items), Positions::List { items: inner, .. }) => {
+            Some(items.iter().zip(inner).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Analyze each expression of a list
+fn analyze_all(items: &[Item]) -> Result<Vec<Rc<Expr>>> {
+    items.iter().map(|(expr, pos)| analyze(expr, pos)).collect()
+}
+
+/// Turn a body into a single expression
+fn sequence(mut body: Vec<Rc<Expr>>) -> Rc<Expr> {
+    match body.len() {
+        0 => Rc::new(Expr::Literal(Value::Nil)),
+        1 => body.remove(0),
+        _ => Rc::new(Expr::Begin(body.into())),
+    }
+}
+
+/// Reference to a variable introduced by the analysis
+fn hidden(name: &str, start: Position) -> Rc<Expr> {
+    Rc::new(Expr::Variable(name.into(), start))
+}
+
+/// Anonymous procedure introduced by the analysis
+fn anonymous(params: Vec<Rc<str>>, body: Rc<Expr>) -> Rc<Expr> {
+    Rc::new(Expr::Lambda(Rc::new(Lambda {
+        name: None,
+        params,
+        rest: None,
+        body,
+    })))
+}
+
+/// Thunk returning the value of `expr`, which is evaluated right away
+fn deferred(expr: Rc<Expr>, start: Position) -> Rc<Expr> {
+    let thunk = anonymous(vec![], hidden(DEFERRED_VALUE, start));
+    Rc::new(Expr::Application(
+        anonymous(vec![DEFERRED_VALUE.into()], thunk),
+        vec![expr].into(),
+        start,
+    ))
+}
+
+/// Evaluate `body` with `exit` bound to an escape continuation, then call the
+/// thunk it returns or the one passed to `exit`. This way the thunk runs
+/// after leaving the dynamic extent of `body`.
+fn escaping(exit: &str, body: Rc<Expr>, start: Position) -> Rc<Expr> {
+    let receiver = anonymous(vec![exit.into()], body);
+    Rc::new(Expr::Application(
+        control(Control::CallEC, vec![receiver], start),
+        vec![].into(),
+        start,
+    ))
+}
+
+/// Call to one of the procedures needing the state of the machine, which
+/// cannot be shadowed by user definitions
+fn control(control: Control, args: Vec<Rc<Expr>>, start: Position) -> Rc<Expr> {
+    let procedure = Rc::new(Expr::Literal(Value::Control(control)));
+    Rc::new(Expr::Application(procedure, args.into(), start))
+}
+
+/// Call to one of the builtins, which cannot be shadowed by user definitions
+fn builtin(builtin: Builtin, args: Vec<Rc<Expr>>, start: Position) -> Rc<Expr> {
+    let procedure = Rc::new(Expr::Literal(Value::Builtin(builtin)));
+    Rc::new(Expr::Application(procedure, args.into(), start))
+}
+
+/// Analyze both `(define name value)` and `(define (name params...) body...)`
+fn analyze_define(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    match args {
+        [(SExpression::Symbol(name), _), (value, pos)] => Ok(Rc::new(Expr::Define(
+            name.as_str().into(),
+            analyze(value, pos)?,
+        ))),
+        // Shorthand for `(define name (lambda params body...))`
+        [(signature, _), body @ ..] => match spine(signature) {
+            Some(([SExpression::Symbol(name), params @ ..], rest)) => {
+                let name: Rc<str> = name.as_str().into();
+                let (params, rest) = parameters(form, params, rest)?;
+                let lambda = make_lambda(form, Some(name.clone()), params, rest, body)?;
+                Ok(Rc::new(Expr::Define(name, lambda)))
+            }
+            _ => Err(form.malformed()),
+        },
+        _ => Err(form.malformed()),
+    }
+}
+
+/// Analyze a lambda expression given its parameters and its body
+fn analyze_lambda(
+    form: &Form,
+    name: Option<Rc<str>>,
+    params: &SExpression,
+    body: &[Item],
+) -> Result<Rc<Expr>> {
+    let (params, rest) = match params {
+        // A single symbol collects all the arguments
+        SExpression::Symbol(rest) => (vec![], Some(rest.as_str().into())),
+        params => match spine(params) {
+            Some((params, rest)) => parameters(form, params, rest)?,
+            None => return Err(form.malformed()),
+        },
+    };
+    make_lambda(form, name, params, rest, body)
+}
+
+/// Elements of a proper or dotted list, along with the last element after
+/// the dot, `None` for atoms
+fn spine(expr: &SExpression) -> Option<(&[SExpression], Option<&SExpression>)> {
+    match expr {
+        SExpression::List(items) => Some((items, None)),
+        SExpression::DottedList(items, tail) => Some((items, Some(tail))),
+        _ => None,
+    }
+}
+
+/// Split a parameter list into the required ones and the rest one, which
+/// follows the dot and collects the extra arguments
+fn parameters(
+    form: &Form,
+    params: &[SExpression],
+    rest: Option<&SExpression>,
+) -> Result<Parameters> {
+    let symbol = |param: &SExpression| match param {
+        SExpression::Symbol(param) => Ok(param.as_str().into()),
+        _ => Err(form.malformed()),
+    };
+    let names = params.iter().map(symbol).collect::<Result<_>>()?;
+    Ok((names, rest.map(symbol).transpose()?))
+}
+
+/// Build a lambda, checking that the parameters are distinct and that the
+/// body is not empty
+fn make_lambda(
+    form: &Form,
+    name: Option<Rc<str>>,
+    params: Vec<Rc<str>>,
+    rest: Option<Rc<str>>,
+    body: &[Item],
+) -> Result<Rc<Expr>> {
+    if body.is_empty() {
+        return Err(form.malformed());
+    }
+    distinct(form, &params, &rest)?;
+    Ok(Rc::new(Expr::Lambda(Rc::new(Lambda {
+        name,
+        params,
+        rest,
+        body: sequence(analyze_all(body)?),
+    }))))
+}
+
+/// Check that the parameters of a procedure are all distinct
+fn distinct(form: &Form, params: &[Rc<str>], rest: &Option<Rc<str>>) -> Result<()> {
+    let all: Vec<&Rc<str>> = params.iter().chain(rest.iter()).collect();
+    if (1..all.len()).any(|i| all[..i].contains(&all[i])) {
+        return Err(form.malformed());
+    }
+    Ok(())
+}
+
+/// Analyze `(cond (test body...)... (else body...))` into nested ifs, ending
+/// with `fallback` when no clause applies
+fn analyze_cond(form: &Form, clauses: &[Item], fallback: Rc<Expr>) -> Result<Rc<Expr>> {
+    let mut result = fallback;
+    // Build the chain from the last clause, which is the innermost
+    for (i, (clause, pos)) in clauses.iter().enumerate().rev() {
+        let clause = elements(clause, pos).ok_or_else(|| form.malformed())?;
+        result = match clause.as_slice() {
+            [(SExpression::Symbol(e), _), body @ ..] if e == "else" => {
+                if i + 1 != clauses.len() || body.is_empty() {
+                    return Err(form.malformed());
+                }
+                sequence(analyze_all(body)?)
+            }
+            // A clause without body evaluates to its test, when true
+            [(test, pos)] => Rc::new(Expr::Or(vec![analyze(test, pos)?, result].into())),
+            // `(test => receiver)` calls receiver with the value of the test
+            [(test, test_pos), (SExpression::Symbol(arrow), _), (receiver, receiver_pos)]
+                if arrow == "=>" =>
+            {
+                let value = hidden(COND_TEST, form.start);
+                let call = Rc::new(Expr::Application(
+                    analyze(receiver, receiver_pos)?,
+                    vec![value.clone()].into(),
+                    form.start,
+                ));
+                let lambda = Rc::new(Expr::Lambda(Rc::new(Lambda {
+                    name: None,
+                    params: vec![COND_TEST.into()],
+                    rest: None,
+                    body: Rc::new(Expr::If(value, call, result)),
+                })));
+                Rc::new(Expr::Application(
+                    lambda,
+                    vec![analyze(test, test_pos)?].into(),
+                    form.start,
+                ))
+            }
+            [(test, pos), body @ ..] => Rc::new(Expr::If(
+                analyze(test, pos)?,
+                sequence(analyze_all(body)?),
+                result,
+            )),
+            [] => return Err(form.malformed()),
+        };
+    }
+    Ok(result)
+}
+
+/// Analyze the template of a quasiquote nested `depth` times. Only the
+/// unquotes at depth one are evaluated, the inner ones are kept as data.
+fn analyze_quasiquote(
+    template: &SExpression,
+    positions: &Positions,
+    depth: usize,
+) -> Result<Rc<Expr>> {
+    let start = positions.start();
+    let items = match (template, positions) {
+        // Nothing to evaluate, so the template is a constant
+        _ if !unquotes(template, depth) => {
+            return Ok(Rc::new(Expr::Literal(Value::from(template))))
+        }
+        (SExpression::Vector(items), Positions::List { items: inner, .. }) => {
+            let items: Vec<Item> = items.iter().zip(inner).collect();
+            let list = analyze_quasiquote_items(&items, None, start, depth)?;
+            return Ok(builtin(builtins::LIST_TO_VECTOR, vec![list], start));
+        }
+        // The positions of a dotted list end with the ones of its tail
+        (SExpression::DottedList(items, tail), Positions::List { items: inner, .. }) => {
+            let items: Vec<Item> = items.iter().zip(inner).collect();
+            let tail = analyze_quasiquote(tail, &inner[items.len()], depth)?;
+            return analyze_quasiquote_items(&items, Some(tail), start, depth);
+        }
+        _ => match elements(template, positions) {
+            Some(items) => items,
+            None => return Ok(Rc::new(Expr::Literal(Value::from(template)))),
+        },
+    };
+    // `(a . ,b)` reads as `(a unquote b)`, whose tail is the unquote
+    if let [init @ .., (SExpression::Symbol(op), _), (inner, pos)] = items.as_slice() {
+        if let Some(tail) = analyze_keyword(op, inner, pos, start, depth)? {
+            return analyze_quasiquote_items(init, Some(tail), start, depth);
+        }
+    }
+    analyze_quasiquote_items(&items, None, start, depth)
+}
+
+/// Analyze the quasiquote form `(op inner)` nested `depth` times when `op`
+/// is one of the quasiquote keywords, `None` otherwise
+fn analyze_keyword(
+    op: &str,
+    inner: &SExpression,
+    positions: &Positions,
+    start: Position,
+    depth: usize,
+) -> Result<Option<Rc<Expr>>> {
+    let depth = match op {
+        "unquote" if depth == 1 => return analyze(inner, positions).map(Some),
+        "unquote-splicing" if depth == 1 => {
+            return Err(EvalError::at(
+                format!("unquote-splicing outside of a list: ,@{}", inner),
+                start,
+            ))
+        }
+        "unquote" | "unquote-splicing" => depth - 1,
+        "quasiquote" => depth + 1,
+        _ => return Ok(None),
+    };
+    let keyword = Rc::new(Expr::Literal(Value::Symbol(op.into())));
+    let inner = analyze_quasiquote(inner, positions, depth)?;
+    Ok(Some(builtin(builtins::LIST, vec![keyword, inner], start)))
+}
+
+/// Analyze the elements of a list or vector template nested `depth` times
+/// into the list of their values, ending with the value of `tail` instead
+/// of the empty list when there is one
+fn analyze_quasiquote_items(
+    items: &[Item],
+    tail: Option<Rc<Expr>>,
+    start: Position,
+    depth: usize,
+) -> Result<Rc<Expr>> {
+    // Consecutive elements are collected in a list, then all the lists are
+    // appended along with the spliced ones
+    let mut segments = vec![];
+    let mut elements = vec![];
+    for &(item, pos) in items {
+        match (item, pos) {
+            (SExpression::List(splice), Positions::List { items: inner, .. })
+                if depth == 1 && splice.len() == 2 && is_symbol(&splice[0], "unquote-splicing") =>
+            {
+                if !elements.is_empty() {
+                    segments.push(builtin(
+                        builtins::LIST,
+                        std::mem::take(&mut elements),
+                        start,
+                    ));
+                }
+                segments.push(analyze(&splice[1], &inner[1])?);
+            }
+            _ => elements.push(analyze_quasiquote(item, pos, depth)?),
+        }
+    }
+    if !elements.is_empty() {
+        segments.push(builtin(builtins::LIST, elements, start));
+    }
+    segments.extend(tail);
+    Ok(match segments.len() {
+        1 => segments.remove(0),
+        _ => builtin(builtins::APPEND, segments, start),
+    })
+}
+
+/// Check whether a quasiquote template nested `depth` times contains
+/// unquotes to evaluate. The template is walked with an explicit stack,
+/// since quoted data can be nested deeper than the native stack allows.
+fn unquotes(template: &SExpression, depth: usize) -> bool {
+    let mut pending = vec![(template, depth)];
+    while let Some((template, depth)) = pending.pop() {
+        let items = match template {
+            SExpression::List(items) => match items.as_slice() {
+                // `(a . ,b)` reads as `(a unquote b)`, whose tail is the unquote
+                [init @ .., SExpression::Symbol(op), inner] => match op.as_str() {
+                    "unquote" | "unquote-splicing" if depth == 1 => return true,
+                    "unquote" | "unquote-splicing" => {
+                        pending.push((inner, depth - 1));
+                        init
+                    }
+                    "quasiquote" => {
+                        pending.push((inner, depth + 1));
+                        init
+                    }
+                    _ => items,
+                },
+                items => items,
+            },
+            SExpression::DottedList(items, tail) => {
+                pending.push((tail, depth));
+                items
+            }
+            SExpression::Vector(items) => items,
+            _ => continue,
+        };
+        pending.extend(items.iter().map(|item| (item, depth)));
+    }
+    false
+}
+
+fn is_symbol(expr: &SExpression, name: &str) -> bool {
+    matches!(expr, SExpression::Symbol(symbol) if symbol == name)
+}
+
+/// Analyze `(case key ((datum...) body...)... (else body...))`, comparing the
+/// key with `eqv?` against each datum
+fn analyze_case(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    let ((key, key_pos), clauses) = args.split_first().ok_or_else(|| form.malformed())?;
+    let eqv = Rc::new(Expr::Literal(Value::Builtin(builtins::EQV)));
+    let mut result = Rc::new(Expr::Literal(Value::Nil));
+    for (i, (clause, pos)) in clauses.iter().enumerate().rev() {
+        let clause = elements(clause, pos).ok_or_else(|| form.malformed())?;
+        result = match clause.as_slice() {
+            [(SExpression::Symbol(e), _), body @ ..] if e == "else" => {
+                if i + 1 != clauses.len() || body.is_empty() {
+                    return Err(form.malformed());
+                }
+                sequence(analyze_all(body)?)
+            }
+            [(SExpression::List(data), _), body @ ..] if !body.is_empty() => {
+                let tests = data
+                    .iter()
+                    .map(|datum| {
+                        Rc::new(Expr::Application(
+                            eqv.clone(),
+                            vec![
+                                hidden(CASE_KEY, form.start),
+                                Rc::new(Expr::Literal(Value::from(datum))),
+                            ]
+                            .into(),
+                            form.start,
+                        ))
+                    })
+                    .collect();
+                Rc::new(Expr::If(
+                    Rc::new(Expr::Or(tests)),
+                    sequence(analyze_all(body)?),
+                    result,
+                ))
+            }
+            _ => return Err(form.malformed()),
+        };
+    }
+    let lambda = Rc::new(Expr::Lambda(Rc::new(Lambda {
+        name: None,
+        params: vec![CASE_KEY.into()],
+        rest: None,
+        body: result,
+    })));
+    Ok(Rc::new(Expr::Application(
+        lambda,
+        vec![analyze(key, key_pos)?].into(),
+        form.start,
+    )))
+}
+
+/// Analyze `(unwind-protect protected cleanup...)` into a `dynamic-wind`
+/// whose `after` thunk runs the cleanup forms
+fn analyze_unwind_protect(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    let ((protected, pos), cleanup) = args.split_first().ok_or_else(|| form.malformed())?;
+    Ok(control(
+        Control::DynamicWind,
+        vec![
+            anonymous(vec![], Rc::new(Expr::Literal(Value::Nil))),
+            anonymous(vec![], analyze(protected, pos)?),
+            anonymous(vec![], sequence(analyze_all(cleanup)?)),
+        ],
+        form.start,
+    ))
+}
+
+/// Analyze `(guard (var clause...) body...)`. When the body raises, control
+/// leaves it and the raised value, bound to `var`, is matched against the
+/// `cond` clauses. When none applies the value is raised again with
+/// `raise-continuable`, from the dynamic environment of the guard.
+fn analyze_guard(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    let ((spec, spec_pos), body) = args.split_first().ok_or_else(|| form.malformed())?;
+    let spec = elements(spec, spec_pos).ok_or_else(|| form.malformed())?;
+    let (var, clauses) = match spec.split_first() {
+        Some(((SExpression::Symbol(var), _), clauses)) => (Rc::<str>::from(var.as_str()), clauses),
+        _ => return Err(form.malformed()),
+    };
+    if body.is_empty() {
+        return Err(form.malformed());
+    }
+    let start = form.start;
+    let reraise = control(
+        Control::RaiseContinuable,
+        vec![Rc::new(Expr::Variable(var.clone(), start))],
+        start,
+    );
+    // The clauses run after the body has been unwound
+    let handler = anonymous(
+        vec![var],
+        Rc::new(Expr::Application(
+            hidden(GUARD_EXIT, start),
+            vec![anonymous(vec![], analyze_cond(form, clauses, reraise)?)].into(),
+            start,
+        )),
+    );
+    let body = anonymous(vec![], deferred(sequence(analyze_all(body)?), start));
+    Ok(escaping(
+        GUARD_EXIT,
+        control(Control::WithExceptionHandler, vec![handler, body], start),
+        start,
+    ))
+}
+
+/// Analyze `(define-condition name (parent) (slot...))`, where the parent is
+/// optional and defaults to `condition`
+fn analyze_define_condition(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    let (name, parents, slots) = match args {
+        [(SExpression::Symbol(name), _), parents, (SExpression::List(slots), _)] => {
+            (name, parents, slots)
+        }
+        _ => return Err(form.malformed()),
+    };
+    let parent = match elements(parents.0, parents.1).as_deref() {
+        Some([]) => Rc::new(Expr::Literal(Value::Nil)),
+        Some([(SExpression::Symbol(parent), pos)]) => {
+            Rc::new(Expr::Variable(parent.as_str().into(), pos.start()))
+        }
+        _ => return Err(form.malformed()),
+    };
+    if !slots
+        .iter()
+        .all(|slot| matches!(slot, SExpression::Symbol(_)))
+    {
+        return Err(form.malformed());
+    }
+    let name: Rc<str> = name.as_str().into();
+    let make = Rc::new(Expr::Literal(Value::Builtin(builtins::MAKE_CONDITION_TYPE)));
+    let kind = Rc::new(Expr::Application(
+        make,
+        vec![
+            Rc::new(Expr::Literal(Value::Symbol(name.clone()))),
+            parent,
+            Rc::new(Expr::Literal(Value::list(
+                slots.iter().map(Value::from).collect(),
+            ))),
+        ]
+        .into(),
+        form.start,
+    ));
+    Ok(Rc::new(Expr::Define(name, kind)))
+}
+
+/// Analyze `(handler-bind ((type handler)...) body...)`. The handlers are
+/// called by `signal` without unwinding the stack, and decline to handle the
+/// condition by returning.
+fn analyze_handler_bind(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    let ((bindings, pos), body) = args.split_first().ok_or_else(|| form.malformed())?;
+    let mut args = vec![anonymous(vec![], sequence(analyze_all(body)?))];
+    for (binding, pos) in elements(bindings, pos).ok_or_else(|| form.malformed())? {
+        match elements(binding, pos).as_deref() {
+            Some([kind, handler]) => {
+                args.push(analyze(kind.0, kind.1)?);
+                args.push(analyze(handler.0, handler.1)?);
+            }
+            _ => return Err(form.malformed()),
+        }
+    }
+    Ok(control(Control::HandlerBind, args, form.start))
+}
+
+/// Analyze `(restart-case expr (name (params...) body...)...)`. Invoking one
+/// of the restarts while `expr` is evaluated leaves it, and the body of the
+/// restart gives the value of the whole form.
+fn analyze_restart_case(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    let ((expr, pos), clauses) = args.split_first().ok_or_else(|| form.malformed())?;
+    let start = form.start;
+    let mut args = vec![anonymous(vec![], deferred(analyze(expr, pos)?, start))];
+    for (clause, pos) in clauses {
+        match elements(clause, pos).as_deref() {
+            Some([(SExpression::Symbol(name), _), (params, _), body @ ..]) => {
+                let name: Rc<str> = name.as_str().into();
+                let (params, rest) = spine(params).ok_or_else(|| form.malformed())?;
+                let (params, rest) = parameters(form, params, rest)?;
+                distinct(form, &params, &rest)?;
+                let exit = Rc::new(Expr::Application(
+                    hidden(RESTART_EXIT, start),
+                    vec![anonymous(vec![], sequence(analyze_all(body)?))].into(),
+                    start,
+                ));
+                args.push(Rc::new(Expr::Literal(Value::Symbol(name.clone()))));
+                args.push(Rc::new(Expr::Lambda(Rc::new(Lambda {
+                    name: Some(name),
+                    params,
+                    rest,
+                    body: exit,
+                }))));
+            }
+            _ => return Err(form.malformed()),
+        }
+    }
+    Ok(escaping(
+        RESTART_EXIT,
+        control(Control::WithRestarts, args, start),
+        start,
+    ))
+}
+
+/// Parse the `((name init)...)` bindings of the let forms
+fn bindings(form: &Form, item: &Item) -> Result<Vec<(Rc<str>, Rc<Expr>)>> {
+    let bindings = elements(item.0, item.1).ok_or_else(|| form.malformed())?;
+    bindings
+        .iter()
+        .map(|(binding, pos)| {
+            match elements(binding, pos)
+                .ok_or_else(|| form.malformed())?
+                .as_slice()
+            {
+                [(SExpression::Symbol(name), _), (init, pos)] => {
+                    Ok((name.as_str().into(), analyze(init, pos)?))
+                }
+                _ => Err(form.malformed()),
+            }
+        })
+        .collect()
+}
+
+/// Analyze `let`, which is the application of a lambda to the initial values.
+/// The named variant binds the lambda to a name visible inside its body.
+fn analyze_let(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    match args {
+        [(SExpression::Symbol(name), _), bindings_item, body @ ..] => {
+            let name: Rc<str> = name.as_str().into();
+            let (params, inits): (Vec<_>, Vec<_>) =
+                bindings(form, bindings_item)?.into_iter().unzip();
+            let lambda = make_lambda(form, Some(name.clone()), params, None, body)?;
+            let call = Rc::new(Expr::Application(
+                Rc::new(Expr::Variable(name.clone(), form.start)),
+                inits.into(),
+                form.start,
+            ));
+            let scope = Rc::new(Expr::Lambda(Rc::new(Lambda {
+                name: None,
+                params: vec![],
+                rest: None,
+                body: sequence(vec![Rc::new(Expr::Define(name, lambda)), call]),
+            })));
+            Ok(Rc::new(Expr::Application(scope, Rc::new([]), form.start)))
+        }
+        [bindings_item, body @ ..] => {
+            let (params, inits): (Vec<_>, Vec<_>) =
+                bindings(form, bindings_item)?.into_iter().unzip();
+            let lambda = make_lambda(form, None, params, None, body)?;
+            Ok(Rc::new(Expr::Application(lambda, inits.into(), form.start)))
+        }
+        _ => Err(form.malformed()),
+    }
+}
+
+/// Analyze `let*` as nested lets, each one binding a single variable
+fn analyze_let_star(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    let (bindings_item, body) = args.split_first().ok_or_else(|| form.malformed())?;
+    let bindings = bindings(form, bindings_item)?;
+    let mut result = make_lambda(form, None, vec![], None, body)?;
+    result = Rc::new(Expr::Application(result, Rc::new([]), form.start));
+    for (name, init) in bindings.into_iter().rev() {
+        let lambda = Rc::new(Expr::Lambda(Rc::new(Lambda {
+            name: None,
+            params: vec![name],
+            rest: None,
+            body: result,
+        })));
+        result = Rc::new(Expr::Application(lambda, vec![init].into(), form.start));
+    }
+    Ok(result)
+}
+
+/// Analyze `letrec` as a new scope whose bindings are internal definitions,
+/// so that every initial value can refer to all the names
+fn analyze_letrec(form: &Form, args: &[Item]) -> Result<Rc<Expr>> {
+    let (bindings_item, body) = args.split_first().ok_or_else(|| form.malformed())?;
+    let bindings = bindings(form, bindings_item)?;
+    let names: Vec<&Rc<str>> = bindings.iter().map(|(name, _)| name).collect();
+    if body.is_empty() || (1..names.len()).any(|i| names[..i].contains(&names[i])) {
+        return Err(form.malformed());
+    }
+    let mut scope: Vec<Rc<Expr>> = bindings
+        .into_iter()
+        .map(|(name, init)| Rc::new(Expr::Define(name, init)))
+        .collect();
+    scope.extend(analyze_all(body)?);
+    let lambda = Rc::new(Expr::Lambda(Rc::new(Lambda {
+        name: None,
+        params: vec![],
+        rest: None,
+        body: sequence(scope),
+    })));
+    Ok(Rc::new(Expr::Application(lambda, Rc::new([]), form.start)))
+}
+
+/// Analyze a procedure call, whose operator is the first item
+fn analyze_application(items: &[Item], start: Position) -> Result<Rc<Expr>> {
+    let operator = analyze(items[0].0, items[0].1)?;
+    let operands = analyze_all(&items[1..])?;
+    Ok(Rc::new(Expr::Application(operator, operands.into(), start)))
+}
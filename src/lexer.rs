@@ -1,195 +1,930 @@
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read};
+
 /// Wrapper to a generic error encountered during the parsing phase
 pub type Result<T> = std::result::Result<T, ParsingError>;
 
-#[derive(Debug, Clone)]
-/// Error while the parsing phase
-pub struct ParsingError(pub String);
+#[derive(Debug)]
+/// Error while the parsing phase, along with the region of the source it
+/// refers to
+pub enum ParsingError {
+    /// String missing its closing quote, which starts at the span
+    UnterminatedString(Span),
+    /// Block comment missing its `|#`, which starts at the span
+    UnterminatedBlockComment(Span),
+    InvalidEscape(Span),
+    /// Escape sequence of a code point that is not a char
+    InvalidCodePoint(Span),
+    InvalidNumber(Span, String),
+    InvalidCharacter(Span, String),
+    /// Unknown syntax introduced by `#`
+    InvalidSyntax(Span, String),
+    InvalidUtf8(Span),
+    /// Failure while reading the source
+    Io(io::Error),
+    UnexpectedCloseParen(Span),
+    /// List whose opening paren is at the span, never closed
+    UnclosedList(Span),
+    /// Quote, quasiquote or unquote not followed by an expression
+    MissingQuoted(Span),
+    /// Datum comment not followed by an expression
+    UnterminatedDatumComment(Span),
+    /// Element of a bytevector that is not an integer between 0 and 255
+    InvalidByte(Span, String),
+    /// Dot not between the elements of a list and its single last one
+    MisplacedDot(Span),
+}
+
+impl ParsingError {
+    /// Region of the source the error refers to, if any
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParsingError::UnterminatedString(span)
+            | ParsingError::UnterminatedBlockComment(span)
+            | ParsingError::InvalidEscape(span)
+            | ParsingError::InvalidCodePoint(span)
+            | ParsingError::InvalidNumber(span, _)
+            | ParsingError::InvalidCharacter(span, _)
+            | ParsingError::InvalidSyntax(span, _)
+            | ParsingError::InvalidUtf8(span)
+            | ParsingError::UnexpectedCloseParen(span)
+            | ParsingError::UnclosedList(span)
+            | ParsingError::MissingQuoted(span)
+            | ParsingError::UnterminatedDatumComment(span)
+            | ParsingError::InvalidByte(span, _)
+            | ParsingError::MisplacedDot(span) => Some(*span),
+            ParsingError::Io(_) => None,
+        }
+    }
+
+    /// Describe the error, without its position
+    pub fn message(&self) -> String {
+        match self {
+            ParsingError::UnterminatedString(_) => String::from("unterminated string"),
+            ParsingError::UnterminatedBlockComment(_) => String::from("unterminated block comment"),
+            ParsingError::InvalidEscape(_) => String::from("invalid escape sequence in string"),
+            ParsingError::InvalidCodePoint(_) => {
+                String::from("invalid code point in string escape")
+            }
+            ParsingError::InvalidNumber(_, text) => format!("invalid number `{}`", text),
+            ParsingError::InvalidCharacter(_, text) => format!("invalid character `{}`", text),
+            ParsingError::InvalidSyntax(_, text) => format!("invalid syntax `{}`", text),
+            ParsingError::InvalidUtf8(_) => String::from("invalid UTF-8 in the source"),
+            ParsingError::Io(error) => format!("cannot read the source: {}", error),
+            ParsingError::UnexpectedCloseParen(_) => {
+                String::from("closing paren without opening it")
+            }
+            ParsingError::UnclosedList(_) => String::from("unclosed '('"),
+            ParsingError::MissingQuoted(_) => {
+                String::from("expected an expression after the quote")
+            }
+            ParsingError::UnterminatedDatumComment(_) => {
+                String::from("expected an expression after the datum comment")
+            }
+            ParsingError::InvalidByte(_, text) => format!("invalid byte `{}` in bytevector", text),
+            ParsingError::MisplacedDot(_) => String::from("misplaced '.'"),
+        }
+    }
+
+    /// Render the error as a diagnostic showing the line of `source` where
+    /// it occurred, with the region it refers to underlined. `file` names
+    /// the source.
+    pub fn render(&self, file: &str, source: &[u8]) -> String {
+        let span = match self.span() {
+            Some(span) => span,
+            None => return format!("error: {}\n --> {}\n", self.message(), file),
+        };
+        let Position { line, column } = span.position;
+        let text = source
+            .split(|&byte| byte == b'\n')
+            .nth((line as usize).saturating_sub(1))
+            .map(String::from_utf8_lossy)
+            .unwrap_or_default();
+        let text = text.trim_end_matches('\r');
+        // Keep the tabs before the region, so that the carets line up
+        let indent: String = text
+            .chars()
+            .take((column as usize).saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // Underline the region up to the end of its first line
+        let mut width = 0;
+        let mut bytes = 0;
+        for c in text.chars().skip((column as usize).saturating_sub(1)) {
+            if bytes >= span.end - span.start {
+                break;
+            }
+            bytes += c.len_utf8();
+            width += 1;
+        }
+        let gutter = " ".repeat(line.to_string().len());
+        format!(
+            "error: {message}\n{gutter}--> {file}:{position}\n{gutter} |\n{line} | {text}\n{gutter} | {indent}{carets}\n",
+            message = self.message(),
+            position = span.position,
+            carets = "^".repeat(width.max(1)),
+        )
+    }
+}
 
 impl std::fmt::Display for ParsingError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Parsing Error: {}", self.0)
+        match self.span() {
+            Some(span) => write!(f, "Parsing Error: {} {}", span.position, self.message()),
+            None => write!(f, "Parsing Error: {}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for ParsingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParsingError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// Position of a token in the source, both line and column start from 1
+pub struct Position {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// Region of the source covered by a token
+pub struct Span {
+    /// Byte offset of the first char
+    pub start: usize,
+    /// Byte offset just past the last char
+    pub end: usize,
+    /// Line and column of the first char
+    pub position: Position,
+}
+
+impl Span {
+    /// Span going from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
     }
 }
 
 #[derive(Debug, PartialEq)]
 /// Token produced by the tokenizer
-pub(crate) enum Token {
+pub enum Token {
     OpenParen,
     Symbol(String),
     CloseParen,
+    /// `#(`, opening a vector
+    OpenVector,
+    /// `#u8(`, opening a bytevector
+    OpenBytevector,
+    /// `#;`, commenting out the expression after it
+    DatumComment,
     Quote,
+    Backquote,
     Comma,
+    /// Comma followed by `@`, splicing a list into a quasiquote
+    CommaAt,
+    /// Dot before the last element of a list, ending it with that element
+    /// instead of the empty list
+    Dot,
     String(String),
+    /// Exact integer
+    Integer(i64),
+    /// Inexact real number
     Number(f64),
-    // @TODO: support integer.
+    Bool(bool),
+    Char(char),
     End,
 }
 
-/// Simple scanner to parse tokens from the source
-pub struct Lexer {
-    /// Source program to scan
-    pub source: String,
-    /// List of tokens generated by the lexer
-    pub(crate) tokens: Vec<Token>,
-    /// The char index at the beginning of the current token parse round
-    start: usize,
-    /// The index of the char currently parsed in the all `source`
+/// Simple scanner producing tokens on demand from a stream of bytes. Only
+/// the token being scanned is kept in memory, so the source can be larger
+/// than memory or never end.
+pub struct Lexer<R: Read> {
+    /// Bytes of the source not decoded yet
+    bytes: io::Bytes<BufReader<R>>,
+    /// Chars decoded ahead of the one at point, along with the number of
+    /// bytes of the source they come from
+    lookahead: VecDeque<(char, usize)>,
+    /// Byte read past a malformed UTF-8 sequence, decoded on its own next
+    pushed_back: Option<u8>,
+    /// Text of the token being scanned
+    lexeme: String,
+    /// Empty span where the token being scanned starts
+    origin: Span,
+    /// The byte offset of the char currently parsed in the all source
     current: usize,
     /// The actual line in the source code
     line: u32,
+    /// The column of the char currently parsed, counted in chars
+    column: u32,
+    /// Failure while reading the source, reported in place of the token
+    /// being scanned. The source counts as ended after an I/O error.
+    error: Option<ParsingError>,
+    /// Whether the source can still be read
+    readable: bool,
+    /// Whether the `End` token was produced
+    done: bool,
 }
 
-impl Lexer {
-    pub fn init(source: &String) -> Self {
-        Lexer {
-            source: source.to_string(),
-            tokens: Vec::new(),
-            start: 0,
-            current: 0,
-            line: 1,
-        }
-    }
+/// The lexer yields each token along with its span, the last one being
+/// `Token::End`
+impl<R: Read> Iterator for Lexer<R> {
+    type Item = Result<(Token, Span)>;
 
-    /// Parse the source into tokens
-    pub fn scan(&mut self) -> Result<()> {
-        while !self.is_end() {
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
             // Make sure to initialize the lexeme start with the current token
-            self.start = self.current;
+            self.origin = self.here();
+            self.lexeme.clear();
             // Perform the scanning
-            self.scan_token()?;
+            let token = self.scan_token();
+            if let Some(error) = self.error.take() {
+                return Some(Err(error));
+            }
+            match token {
+                Ok(Some(token)) => return Some(Ok((token, self.span()))),
+                // Whitespaces and comments produce no token
+                Ok(None) => (),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        None
+    }
+}
+
+impl<R: Read> Lexer<R> {
+    pub fn new(source: R) -> Self {
+        Lexer {
+            bytes: BufReader::new(source).bytes(),
+            lookahead: VecDeque::new(),
+            pushed_back: None,
+            lexeme: String::new(),
+            origin: Span::default(),
+            current: 0,
+            line: 1,
+            column: 1,
+            error: None,
+            readable: true,
+            done: false,
         }
-        self.tokens.push(Token::End);
-        Ok(())
     }
 
     /// Scan a token at point
-    fn scan_token(&mut self) -> Result<()> {
+    fn scan_token(&mut self) -> Result<Option<Token>> {
         if let Some(c) = self.advance() {
-            match c {
-                '(' => self.tokens.push(Token::OpenParen),
-                ')' => self.tokens.push(Token::CloseParen),
-                '\'' => self.tokens.push(Token::Quote),
-                ',' => self.tokens.push(Token::Comma),
+            Ok(Some(match c {
+                '(' => Token::OpenParen,
+                ')' => Token::CloseParen,
+                '\'' => Token::Quote,
+                '`' => Token::Backquote,
+                ',' if self.peek() == '@' => {
+                    self.advance();
+                    Token::CommaAt
+                }
+                ',' => Token::Comma,
                 '"' => self.scan_string()?,
-                ';' => self.skip_comment(),
-                '0'..='9' => self.scan_number()?,
+                '#' if self.peek() == '|' => {
+                    self.advance();
+                    self.skip_block_comment()?;
+                    return Ok(None);
+                }
+                '#' => self.scan_hash()?,
+                ';' => {
+                    self.skip_comment();
+                    return Ok(None);
+                }
                 // Ignore whitespaces
-                ' ' | '\t' | '\r' => (),
-                '\n' => self.line += 1,
+                ' ' | '\t' | '\r' | '\n' => return Ok(None),
                 // Every else is a symbol
-                _ => self.scan_symbol(),
-            }
-            Ok(())
+                _ => self.scan_atom()?,
+            }))
         } else {
-            Err(ParsingError(String::from("Geniric error while parsing")))
+            self.done = true;
+            Ok(Some(Token::End))
         }
     }
 
-    /// Scan a generic symbol
-    fn scan_symbol(&mut self) {
-        while ([' ', '(', ')', '\n'].iter().all(|&s| s != self.peek())) & (!self.is_end()) {
-            // Remember to keep incrementing lines
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
-            self.current += 1;
+    /// Scan a symbol or a number, both extending up to the next delimiter
+    fn scan_atom(&mut self) -> Result<Token> {
+        while !is_delimiter(self.peek()) && !self.is_end() {
+            self.advance();
         }
-        // Extract the substring
-        self.tokens.push(Token::Symbol(
-            self.source
-                .chars()
-                .skip(self.start)
-                .take(self.current - self.start)
-                .collect(),
-        ))
+        if self.lexeme == "." {
+            return Ok(Token::Dot);
+        }
+        if !is_numeric(&self.lexeme) {
+            return Ok(Token::Symbol(self.lexeme.clone()));
+        }
+        number(&self.lexeme)
+            .ok_or_else(|| ParsingError::InvalidNumber(self.span(), self.lexeme.clone()))
     }
 
-    /// Scan a number
-    fn scan_number(&mut self) -> Result<()> {
-        while self.peek().is_ascii_digit() {
-            self.current += 1;
-        }
-        if (self.peek() == '.') & (self.peek_next().is_ascii_digit()) {
-            self.current += 1;
-            while self.peek().is_ascii_digit() {
-                self.current += 1;
+    /// Scan the syntax introduced by `#`: vectors, bytevectors, booleans,
+    /// characters and numbers with a prefix
+    fn scan_hash(&mut self) -> Result<Token> {
+        match self.peek() {
+            '(' => {
+                self.advance();
+                return Ok(Token::OpenVector);
+            }
+            ';' => {
+                self.advance();
+                return Ok(Token::DatumComment);
+            }
+            '\\' => {
+                self.advance();
+                return self.scan_char();
             }
+            _ => (),
+        }
+        while !is_delimiter(self.peek()) && !self.is_end() {
+            self.advance();
+        }
+        if self.lexeme == "#u8" && self.peek() == '(' {
+            self.advance();
+            return Ok(Token::OpenBytevector);
+        }
+        match self.lexeme.as_str() {
+            "#t" | "#true" => Ok(Token::Bool(true)),
+            "#f" | "#false" => Ok(Token::Bool(false)),
+            lexeme if is_numeric(lexeme) => number(lexeme)
+                .ok_or_else(|| ParsingError::InvalidNumber(self.span(), lexeme.to_string())),
+            lexeme => Err(ParsingError::InvalidSyntax(self.span(), lexeme.to_string())),
         }
-        let number: String = self
-            .source
-            .chars()
-            .skip(self.start)
-            .take(self.current - self.start)
-            .collect();
-        self.tokens.push(Token::Number(match number.parse() {
-            Ok(it) => it,
-            Err(_) => {
-                return Err(ParsingError(format!(
-                    "{} Error while parsing a number",
-                    self.line,
-                )))
-            }
-        }));
-        Ok(())
     }
 
-    /// Return the next char in the source file without consuming it
-    fn peek_next(&self) -> char {
-        self.source.chars().nth(self.current + 1).unwrap_or('\0')
+    /// Scan a character after `#\`, either a single char, even a delimiter,
+    /// its name or its hexadecimal code point as in `#\x3bb`
+    fn scan_char(&mut self) -> Result<Token> {
+        let c = self
+            .advance()
+            .ok_or_else(|| ParsingError::InvalidCharacter(self.span(), self.lexeme.clone()))?;
+        while !is_delimiter(self.peek()) && !self.is_end() {
+            self.advance();
+        }
+        let name = &self.lexeme[2..];
+        if name.len() == c.len_utf8() {
+            return Ok(Token::Char(c));
+        }
+        let named = match name {
+            "alarm" => Some('\u{7}'),
+            "backspace" => Some('\u{8}'),
+            "delete" => Some('\u{7f}'),
+            "escape" => Some('\u{1b}'),
+            "newline" => Some('\n'),
+            "null" => Some('\0'),
+            "return" => Some('\r'),
+            "space" => Some(' '),
+            "tab" => Some('\t'),
+            _ => name
+                .strip_prefix('x')
+                .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                .and_then(char::from_u32),
+        };
+        named
+            .map(Token::Char)
+            .ok_or_else(|| ParsingError::InvalidCharacter(self.span(), self.lexeme.clone()))
     }
 
     /// Scan a string
-    fn scan_string(&mut self) -> Result<()> {
+    fn scan_string(&mut self) -> Result<Token> {
+        // The contents of the string, with the escape sequences decoded
+        let mut contents = String::new();
+        // First invalid escape sequence, reported once the string is over so
+        // that scanning resumes after it
+        let mut invalid = None;
         // Parse until the next "
-        while (self.peek() != '"') & (!self.is_end()) {
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
-            self.current += 1;
-        }
-        if self.is_end() {
-            // Error condition, we scanned all the program but no " was found
-            return Err(ParsingError(format!(
-                "{} Error while parsing a string",
-                self.line,
-            )));
-        }
-        // Skip the closing "
-        self.current += 1;
-        self.tokens.push(Token::String(
-            self.source
-                .chars()
-                .skip(self.start)
-                .take(self.current - self.start)
-                .collect(),
-        ));
-        Ok(())
+        loop {
+            let mark = self.here();
+            match self.advance() {
+                Some('"') => {
+                    return match invalid {
+                        Some(error) => Err(error),
+                        None => Ok(Token::String(contents)),
+                    }
+                }
+                Some('\\') => match self.escape(mark) {
+                    Ok(c) => contents.extend(c),
+                    Err(error) => {
+                        invalid.get_or_insert(error);
+                    }
+                },
+                Some(c) => contents.push(c),
+                // Error condition, we scanned all the program but no " was found
+                None => return Err(ParsingError::UnterminatedString(self.opening(1))),
+            }
+        }
+    }
+
+    /// Decode the escape sequence starting with the backslash at `mark`. A
+    /// backslash at the end of a line joins it to the next one, skipping its
+    /// leading whitespaces, so it gives no char.
+    fn escape(&mut self, mark: Span) -> Result<Option<char>> {
+        let c = match self.advance() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('a') => '\u{7}',
+            Some('b') => '\u{8}',
+            Some('0') => '\0',
+            // `\x41;` as in Scheme
+            Some('x') => self.code_point(mark, ';')?,
+            // `\u{41}` as in Rust
+            Some('u') if self.peek() == '{' => {
+                self.advance();
+                self.code_point(mark, '}')?
+            }
+            Some('\n') => {
+                while matches!(self.peek(), ' ' | '\t') {
+                    self.advance();
+                }
+                return Ok(None);
+            }
+            _ => return Err(ParsingError::InvalidEscape(self.since(mark))),
+        };
+        Ok(Some(c))
+    }
+
+    /// Decode the hexadecimal code point of the escape sequence starting at
+    /// `mark`, up to `terminator`
+    fn code_point(&mut self, mark: Span, terminator: char) -> Result<char> {
+        let mut digits = String::new();
+        // A char that does not belong to the sequence is left for the
+        // string, since it may be the closing quote
+        loop {
+            match self.peek() {
+                c if c == terminator => {
+                    self.advance();
+                    break;
+                }
+                c if c.is_ascii_hexdigit() && digits.len() < 6 => {
+                    self.advance();
+                    digits.push(c);
+                }
+                _ => break digits.clear(),
+            }
+        }
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| ParsingError::InvalidCodePoint(self.since(mark)))
     }
 
     /// Skip the comment section
     fn skip_comment(&mut self) {
         while (self.peek() != '\n') & (!self.is_end()) {
-            self.current += 1;
+            self.advance();
+        }
+    }
+
+    /// Skip a block comment, along with the ones nested inside it
+    fn skip_block_comment(&mut self) -> Result<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.advance() {
+                Some('|') if self.peek() == '#' => {
+                    self.advance();
+                    depth -= 1;
+                }
+                Some('#') if self.peek() == '|' => {
+                    self.advance();
+                    depth += 1;
+                }
+                Some(_) => (),
+                None => return Err(ParsingError::UnterminatedBlockComment(self.opening(2))),
+            }
+            // The comment is not part of any token, so it is not kept
+            self.lexeme.clear();
+        }
+        Ok(())
+    }
+
+    /// Position of the char at point
+    fn point(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Empty span at point
+    fn here(&self) -> Span {
+        Span {
+            start: self.current,
+            end: self.current,
+            position: self.point(),
+        }
+    }
+
+    /// Span going from `mark` to point
+    fn since(&self, mark: Span) -> Span {
+        Span {
+            end: self.current,
+            ..mark
+        }
+    }
+
+    /// Span of the current lexeme
+    fn span(&self) -> Span {
+        self.since(self.origin)
+    }
+
+    /// Span of the first `width` bytes of the current lexeme, which open it
+    fn opening(&self, width: usize) -> Span {
+        Span {
+            end: self.origin.start + width,
+            ..self.origin
         }
-        // We do not advance the cursor here, since we want the callee to
-        // advance the total line number
     }
 
     /// Check if the scanner is completed, that is, all the chars have been read
-    fn is_end(&self) -> bool {
-        self.current >= self.source.len()
+    fn is_end(&mut self) -> bool {
+        self.fill(1);
+        self.lookahead.is_empty()
     }
 
     /// Advance the scanner, retuting the char at point
     fn advance(&mut self) -> Option<char> {
-        let c = self.source.chars().nth(self.current);
-        self.current += 1;
-        c
+        self.fill(1);
+        let (c, width) = self.lookahead.pop_front()?;
+        self.lexeme.push(c);
+        self.current += width;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
     }
 
     /// Return the current char the scanner is poining to, without advancing the
     /// iterator
-    fn peek(&self) -> char {
-        self.source.chars().nth(self.current).unwrap_or('\0')
+    fn peek(&mut self) -> char {
+        self.fill(1);
+        self.lookahead.front().map_or('\0', |&(c, _)| c)
+    }
+
+    /// Decode chars until `n` of them are ahead, or the source ends
+    fn fill(&mut self, n: usize) {
+        while self.lookahead.len() < n && self.readable {
+            match self.decode() {
+                Ok(Some(c)) => self.lookahead.push_back(c),
+                Ok(None) => self.readable = false,
+                Err(error) => {
+                    self.error = Some(error);
+                    self.readable = false;
+                }
+            }
+        }
+    }
+
+    /// Decode the next UTF-8 char of the source along with the number of
+    /// bytes encoding it, `None` at its end. The lookahead is empty, so the
+    /// char is at point.
+    fn decode(&mut self) -> Result<Option<(char, usize)>> {
+        let first = match self.byte()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        // The leading byte tells the length of the encoding
+        let width = match first {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 0,
+        };
+        let mut buffer = [first, 0, 0, 0];
+        let mut read = 1;
+        while read < width {
+            match self.byte()? {
+                Some(byte) if byte & 0xC0 == 0x80 => {
+                    buffer[read] = byte;
+                    read += 1;
+                }
+                // The sequence is cut short, and the byte ending it starts
+                // the next char
+                byte => {
+                    self.pushed_back = byte;
+                    break;
+                }
+            }
+        }
+        match std::str::from_utf8(&buffer[..read]) {
+            Ok(decoded) if read == width => Ok(decoded.chars().next().map(|c| (c, width))),
+            // The malformed bytes are replaced, so that the chars after them
+            // can still be scanned, and the error reported with the token
+            _ => {
+                let span = Span {
+                    end: self.current + read,
+                    ..self.here()
+                };
+                self.error.get_or_insert(ParsingError::InvalidUtf8(span));
+                Ok(Some((char::REPLACEMENT_CHARACTER, read)))
+            }
+        }
+    }
+
+    /// Read the next byte of the source
+    fn byte(&mut self) -> Result<Option<u8>> {
+        if let Some(byte) = self.pushed_back.take() {
+            return Ok(Some(byte));
+        }
+        self.bytes.next().transpose().map_err(ParsingError::Io)
+    }
+}
+
+/// Chars ending a symbol or a number
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';')
+}
+
+/// Whether an atom must be read as a number, that is, when it starts with a
+/// digit, with a sign or a dot followed by a digit, or with a prefix
+fn is_numeric(text: &str) -> bool {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('0'..='9'), _, _)
+        | (Some('+' | '-' | '.'), Some('0'..='9'), _)
+        | (Some('+' | '-'), Some('.'), Some('0'..='9')) => true,
+        (Some('#'), Some(prefix), _) => "xXbBoOdDeEiI".contains(prefix),
+        _ => matches!(text, "+inf.0" | "-inf.0" | "+nan.0" | "-nan.0"),
+    }
+}
+
+/// Read a numeric literal, `None` when it is malformed. It may start with a
+/// radix prefix among `#x`, `#b`, `#o` and `#d`, and an exactness one among
+/// `#e` and `#i`, while `_` can separate its digits.
+fn number(text: &str) -> Option<Token> {
+    let mut radix = None;
+    let mut exact = None;
+    let mut rest = text;
+    while let Some(prefixed) = rest.strip_prefix('#') {
+        let mut chars = prefixed.chars();
+        match chars.next()?.to_ascii_lowercase() {
+            'x' if radix.is_none() => radix = Some(16),
+            'b' if radix.is_none() => radix = Some(2),
+            'o' if radix.is_none() => radix = Some(8),
+            'd' if radix.is_none() => radix = Some(10),
+            'e' if exact.is_none() => exact = Some(true),
+            'i' if exact.is_none() => exact = Some(false),
+            _ => return None,
+        }
+        rest = chars.as_str();
+    }
+    let radix = radix.unwrap_or(10);
+    let digits = without_separators(rest, radix)?;
+    let unsigned = digits.strip_prefix(['+', '-']).unwrap_or(&digits);
+    let token = match digits.as_str() {
+        "+inf.0" => Token::Number(f64::INFINITY),
+        "-inf.0" => Token::Number(f64::NEG_INFINITY),
+        "+nan.0" | "-nan.0" => Token::Number(f64::NAN),
+        _ if !unsigned.is_empty() && unsigned.chars().all(|c| c.is_digit(radix)) => {
+            match i64::from_str_radix(&digits, radix) {
+                Ok(n) => Token::Integer(n),
+                // Integers too large for 64 bits can only be inexact
+                Err(_) => Token::Number(big_integer(unsigned, radix, digits.starts_with('-'))),
+            }
+        }
+        _ if radix == 10 && is_decimal(&digits) => Token::Number(digits.parse().ok()?),
+        _ => return None,
+    };
+    match (exact, token) {
+        (Some(true), Token::Number(n)) => {
+            // Without rationals, only integral values have an exact form
+            // `i64::MAX` is rounded up to 2^63 as a float, which is too large
+            let integral = n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64;
+            integral.then_some(Token::Integer(n as i64))
+        }
+        (Some(false), Token::Integer(n)) => Some(Token::Number(n as f64)),
+        (_, token) => Some(token),
+    }
+}
+
+/// Remove the `_` separating the digits, `None` when one is not between two
+/// digits
+fn without_separators(text: &str, radix: u32) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let between_digits = |i: usize| {
+        i > 0 && chars[i - 1].is_digit(radix) && chars.get(i + 1).is_some_and(|c| c.is_digit(radix))
+    };
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '_' && !between_digits(i) {
+            return None;
+        }
+    }
+    Some(text.replace('_', ""))
+}
+
+/// Whether `text` is a decimal with a fraction or an exponent, as `-1.5`,
+/// `.5`, `2.` or `1e10`
+fn is_decimal(text: &str) -> bool {
+    let text = text.strip_prefix(['+', '-']).unwrap_or(text);
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(i) => (&text[..i], Some(&text[i + 1..])),
+        None => (text, None),
+    };
+    let (integral, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    let exponent = exponent.is_none_or(|e| {
+        let e = e.strip_prefix(['+', '-']).unwrap_or(e);
+        !e.is_empty() && digits(e)
+    });
+    !(integral.is_empty() && fraction.is_empty())
+        && digits(integral)
+        && digits(fraction)
+        && exponent
+}
+
+/// Approximate value of an integer too large for 64 bits
+fn big_integer(digits: &str, radix: u32, negative: bool) -> f64 {
+    let magnitude = digits.chars().fold(0.0, |acc, c| {
+        acc * radix as f64 + c.to_digit(radix).unwrap_or(0) as f64
+    });
+    if negative {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tokens of `source` along with their byte offsets, line and column
+    fn tokens(source: &str) -> Vec<(Token, usize, usize, u32, u32)> {
+        Lexer::new(source.as_bytes())
+            .map(|token| {
+                let (token, span) = token.unwrap_or_else(|e| panic!("{}", e));
+                let Position { line, column } = span.position;
+                (token, span.start, span.end, line, column)
+            })
+            .collect()
+    }
+
+    fn symbol(name: &str) -> Token {
+        Token::Symbol(String::from(name))
+    }
+
+    #[test]
+    fn multibyte_atoms() {
+        assert_eq!(
+            tokens("(λ \"héllo\" 日本)"),
+            vec![
+                (Token::OpenParen, 0, 1, 1, 1),
+                (symbol("λ"), 1, 3, 1, 2),
+                (Token::String(String::from("héllo")), 4, 12, 1, 4),
+                (symbol("日本"), 13, 19, 1, 12),
+                (Token::CloseParen, 19, 20, 1, 14),
+                (Token::End, 20, 20, 1, 15),
+            ]
+        );
+    }
+
+    #[test]
+    fn columns_count_chars() {
+        assert_eq!(
+            tokens("😀 ü\n  ñ ; ö\n\"√\n\" #\\λ"),
+            vec![
+                (symbol("😀"), 0, 4, 1, 1),
+                (symbol("ü"), 5, 7, 1, 3),
+                (symbol("ñ"), 10, 12, 2, 3),
+                (Token::String(String::from("√\n")), 18, 24, 3, 1),
+                (Token::Char('λ'), 25, 29, 4, 3),
+                (Token::End, 29, 29, 4, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn multibyte_escapes() {
+        assert_eq!(
+            tokens("\"\\x3bb;\\u{1F600}é\" ß"),
+            vec![
+                (Token::String(String::from("λ😀é")), 0, 19, 1, 1),
+                (symbol("ß"), 20, 22, 1, 20),
+                (Token::End, 22, 22, 1, 21),
+            ]
+        );
+    }
+
+    /// Byte offsets and column of the invalid UTF-8 reported for `source`,
+    /// followed by the tokens scanned after it
+    fn invalid_utf8(source: &[u8]) -> ((usize, usize, u32), Vec<Token>) {
+        let mut lexer = Lexer::new(source);
+        let span = loop {
+            match lexer.next() {
+                Some(Err(ParsingError::InvalidUtf8(span))) => break span,
+                Some(_) => (),
+                None => panic!("no invalid UTF-8 reported"),
+            }
+        };
+        let rest = lexer.map(|token| token.unwrap().0).collect();
+        ((span.start, span.end, span.position.column), rest)
+    }
+
+    #[test]
+    fn malformed_utf8_keeps_the_bytes_after_it() {
+        // The sequence is cut short by an ASCII char, which is still read
+        assert_eq!(
+            invalid_utf8(b"\"\xc3(\" x"),
+            ((1, 2, 2), vec![symbol("x"), Token::End])
+        );
+        assert_eq!(
+            invalid_utf8(b"(a \xe2\x82) b"),
+            ((3, 5, 4), vec![Token::CloseParen, symbol("b"), Token::End])
+        );
+        // A leading byte that no char starts with
+        assert_eq!(invalid_utf8(b"\xff\xc3\xa9"), ((0, 1, 1), vec![Token::End]));
+        // The spans after the malformed bytes are still right
+        let tokens: Vec<_> = Lexer::new(&b"\xe2 \xce\xbb"[..]).collect();
+        assert!(matches!(tokens[0], Err(ParsingError::InvalidUtf8(_))));
+        assert!(matches!(
+            &tokens[1],
+            Ok((Token::Symbol(s), Span { start: 2, end: 4, .. })) if s == "λ"
+        ));
+    }
+
+    #[test]
+    fn exact_numbers_within_64_bits() {
+        assert_eq!(number("#e1e3"), Some(Token::Integer(1000)));
+        assert_eq!(
+            number("#e-9223372036854775808.0"),
+            Some(Token::Integer(i64::MIN))
+        );
+        assert_eq!(
+            number("#e9223372036854774784.0"),
+            Some(Token::Integer(9223372036854774784))
+        );
+        assert_eq!(number("#e9223372036854775808.0"), None);
+        assert_eq!(number("#e-1e19"), None);
+        assert_eq!(number("#e1.5"), None);
+    }
+
+    /// Diagnostic of the first error found in `source`
+    fn rendered(source: &str) -> String {
+        let error = Lexer::new(source.as_bytes())
+            .find_map(Result::err)
+            .expect("an error in the source");
+        error.render("test.rlisp", source.as_bytes())
+    }
+
+    #[test]
+    fn render_error_line() {
+        assert_eq!(
+            rendered("(list 1\n  (+ 2 1x))"),
+            "error: invalid number `1x`\n --> test.rlisp:2:8\n  |\n2 |   (+ 2 1x))\n  |        ^^\n"
+        );
+    }
+
+    #[test]
+    fn render_gutter_as_wide_as_the_line_number() {
+        let source = format!("{}(f 1x)", "\n".repeat(11));
+        assert_eq!(
+            rendered(&source),
+            "error: invalid number `1x`\n  --> test.rlisp:12:4\n   |\n12 | (f 1x)\n   |    ^^\n"
+        );
+    }
+
+    #[test]
+    fn render_keeps_tabs_in_the_indentation() {
+        assert_eq!(
+            rendered("\t(f\t1x)"),
+            "error: invalid number `1x`\n --> test.rlisp:1:5\n  |\n1 | \t(f\t1x)\n  | \t  \t^^\n"
+        );
+    }
+
+    #[test]
+    fn render_one_caret_per_char() {
+        assert_eq!(
+            rendered("(f #\\λλ)"),
+            "error: invalid character `#\\λλ`\n --> test.rlisp:1:4\n  |\n1 | (f #\\λλ)\n  |    ^^^^\n"
+        );
+    }
+
+    #[test]
+    fn render_error_without_span() {
+        let error = ParsingError::Io(std::io::Error::other("disk on fire"));
+        assert_eq!(
+            error.render("test.rlisp", b""),
+            "error: cannot read the source: disk on fire\n --> test.rlisp\n"
+        );
     }
 }
@@ -1,50 +1,232 @@
 use std::env;
 use std::fs;
 use std::io::{self, Write};
+use std::process;
+use std::thread;
 
+mod builtins;
+mod environment;
+mod eval;
+mod expr;
 mod lexer;
 mod parser;
+#[cfg(test)]
+mod random;
+mod value;
 
 /// Enter the REPL
-fn run_repl() {
+fn run_repl(machine: &mut eval::Machine) {
+    let env = eval::global_environment();
+    machine.set_debugger(Box::new(choose_restart));
     let mut input = String::new();
     loop {
-        print!("> ");
+        // Lines continuing an expression get no prompt
+        print!("{}", if input.is_empty() { "> " } else { "  " });
         // Flush to print the output
         io::stdout().flush().unwrap();
-        io::stdin()
+        let read = io::stdin()
             .read_line(&mut input)
             .expect("Cannot read from stdin");
-        run(&input);
+        // Keep reading until the expressions of the input are complete, or
+        // the stream is closed
+        if read > 0 && incomplete(&input) {
+            continue;
+        }
+        run(
+            input.as_bytes(),
+            &Source::Input(&input),
+            true,
+            &env,
+            machine,
+        );
+        // Nothing was read, so the input stream is closed
+        if read == 0 {
+            println!();
+            break;
+        }
         // Remembder to clear the input, otherwise the last insertion will be
         // read again
         input.clear();
     }
 }
 
-/// Scan the input program
-fn run(program: &String) {
-    let mut scanner: lexer::Lexer = lexer::Lexer::init(program);
-    if let Err(e) = scanner.scan() {
-        eprintln!("Error while scanning: {}", e);
+/// Whether the input ends within a list, a string or a block comment, so it
+/// goes on in the next lines
+fn incomplete(input: &str) -> bool {
+    parser::Parser::init(lexer::Lexer::new(input.as_bytes())).any(|parsed| {
+        matches!(
+            parsed,
+            Err(lexer::ParsingError::UnclosedList(_)
+                | lexer::ParsingError::UnterminatedString(_)
+                | lexer::ParsingError::UnterminatedBlockComment(_))
+        )
+    })
+}
+
+/// Show an error nobody handled and let the user pick one of the restarts
+/// it offers by its number, followed by its arguments, or abort the
+/// evaluation with an empty line. The arguments are read as quoted data.
+fn choose_restart(error: &eval::EvalError) -> Option<(usize, Vec<value::Value>)> {
+    eprintln!("{}", error);
+    print!("restart> ");
+    io::stdout().flush().unwrap();
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice).ok()?;
+    let choice = choice.trim();
+    let (index, args) = choice
+        .split_once(char::is_whitespace)
+        .unwrap_or((choice, ""));
+    let index = index.parse().ok()?;
+    let args = parser::Parser::init(lexer::Lexer::new(args.as_bytes()))
+        .map(|parsed| parsed.map(|(expr, _)| value::Value::from(&expr)))
+        .collect::<Result<_, _>>()
+        .map_err(|e| eprint!("{}", e.render("<stdin>", args.as_bytes())))
+        .ok()?;
+    Some((index, args))
+}
+
+/// Where a program comes from, used to show the lines of its syntax errors
+enum Source<'a> {
+    /// Lines typed in the REPL
+    Input(&'a str),
+    /// Path of a file, read again only to show an error
+    File(&'a str),
+}
+
+impl Source<'_> {
+    /// Render a syntax error along with the line where it occurred
+    fn render(&self, error: &lexer::ParsingError) -> String {
+        match self {
+            Source::Input(input) => error.render("<stdin>", input.as_bytes()),
+            Source::File(path) => match fs::read(path) {
+                Ok(source) => error.render(path, &source),
+                Err(_) => format!("{}\n", error),
+            },
+        }
     }
-    println!("{:?}\n\n", scanner.tokens);
-    let mut parser: parser::Parser = parser::Parser::init(scanner.tokens);
-    match parser.parse() {
-        Ok(expr) => println!("{:?}", expr),
-        Err(e) => eprintln!("Error while parsing: {}", e),
+}
+
+/// Scan, parse and evaluate the input program, which comes from `source`,
+/// one top-level expression at a time until the first error. Print the value
+/// of each expression when `echo` is set, otherwise only the last one.
+fn run(
+    program: impl io::Read,
+    source: &Source,
+    echo: bool,
+    env: &environment::Environment,
+    machine: &mut eval::Machine,
+) {
+    let mut last = None;
+    for parsed in parser::Parser::init(lexer::Lexer::new(program)) {
+        let (expr, positions) = match parsed {
+            Ok(located) => located,
+            Err(e) => {
+                eprint!("{}", source.render(&e));
+                return;
+            }
+        };
+        match machine
+            .analyze(&expr, &positions)
+            .and_then(|expr| machine.eval(&expr, env))
+        {
+            Ok(value) if echo => println!("{}", value),
+            Ok(value) => last = Some(value),
+            // The debugger already showed the error it was asked about
+            Err(e) if e.reported => return,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    }
+    if let Some(value) = last {
+        println!("{}", value);
     }
 }
 
+/// Report every syntax error of the input program without evaluating it,
+/// returning whether there was none
+fn check(program: impl io::Read, source: &Source) -> bool {
+    let errors = parser::Parser::init(lexer::Lexer::new(program)).diagnose();
+    for error in &errors {
+        eprint!("{}", source.render(error));
+    }
+    errors.is_empty()
+}
+
+/// Print the expressions of the input program as the reader sees them,
+/// returning whether it has no syntax error
+fn print_forms(program: impl io::Read, source: &Source) -> bool {
+    for parsed in parser::Parser::init(lexer::Lexer::new(program)) {
+        match parsed {
+            Ok((expr, _)) => println!("{}", expr),
+            Err(e) => {
+                eprint!("{}", source.render(&e));
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Read the memory budget of the stacks from the environment
+fn stack_limit() -> usize {
+    env::var("RS_LISP_STACK_LIMIT")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(eval::DEFAULT_STACK_LIMIT)
+}
+
+/// Native stack of the interpreter thread on top of the memory budget,
+/// which the analysis of deeply nested expressions may use up. Evaluation
+/// and printing keep their stacks on the heap.
+const NATIVE_STACK_RESERVE: usize = 8 * 1024 * 1024;
+
 fn main() {
+    let stack_limit = stack_limit();
+    let interpreter = thread::Builder::new()
+        .stack_size(stack_limit.saturating_add(NATIVE_STACK_RESERVE))
+        .spawn(move || start(stack_limit))
+        .expect("Cannot spawn the interpreter thread");
+    // Propagate a panic of the interpreter
+    if interpreter.join().is_err() {
+        process::exit(101);
+    }
+}
+
+/// Run the file given on the command line, or the REPL without arguments.
+/// With `--check` before the file, only report its syntax errors, and with
+/// `--read` print its expressions as they are read.
+fn start(stack_limit: usize) {
+    let mut machine = eval::Machine::new(stack_limit);
     let mut args = env::args();
-    if args.len() == 2 {
-        // Try to parse the input file
-        let content = fs::read_to_string(args.nth(1).expect("Never happen!"))
-            .unwrap_or_else(|_| panic!("Invalid string while reading"));
-        run(&content)
+    if args.len() == 3 {
+        let mode = args.nth(1).expect("Never happen!");
+        let path = args.next().expect("Never happen!");
+        let file =
+            fs::File::open(&path).unwrap_or_else(|e| panic!("Cannot open the input file: {}", e));
+        let valid = match mode.as_str() {
+            "--check" => check(file, &Source::File(&path)),
+            "--read" => print_forms(file, &Source::File(&path)),
+            _ => panic!("Unknown option {}", mode),
+        };
+        if !valid {
+            process::exit(1);
+        }
+    } else if args.len() == 2 {
+        // Try to parse the input file, which is read while lexing
+        let path = args.nth(1).expect("Never happen!");
+        let file =
+            fs::File::open(&path).unwrap_or_else(|e| panic!("Cannot open the input file: {}", e));
+        run(
+            file,
+            &Source::File(&path),
+            false,
+            &eval::global_environment(),
+            &mut machine,
+        )
     } else {
         // Start the REPL
-        run_repl()
+        run_repl(&mut machine)
     }
 }
@@ -1,68 +1,798 @@
-// expression -> atom | "(" list ")"
-// list -> expression*
-// atom -> NUMBERS | STRINGS | SYMBOLS
+// program -> expression*
+// expression -> atom | "(" list ")" | prefix expression
+// prefix -> "'" | "`" | "," | ",@"
+// list -> expression* | expression+ "." expression
+// atom -> NUMBERS | STRINGS | SYMBOLS | BOOLEANS | CHARACTERS
+//       | "#(" list ")" | "#u8(" list ")"
 // SYMBOLS -> ("*", "/", "+", "-", "==", "/=", "t" | "nil")
+// A datum comment "#;" skips the expression after it
 
-use crate::lexer::{Token, ParsingError, Result};
+use std::io::Read;
 
+use crate::lexer::{Lexer, Position, Span, Token, ParsingError, Result};
 
-pub struct Parser {
-    pub tokens: Vec<Token>,
-    pub cursor: usize,
+/// Parser pulling the tokens from the lexer as it needs them
+pub struct Parser<R: Read> {
+    tokens: Lexer<R>,
+    /// Span of the last token pulled
+    last: Span,
+    /// Errors found so far when recovering from them, `None` when the
+    /// first one stops the parsing
+    errors: Option<Vec<ParsingError>>,
+    /// Whether the parsing stopped, at the end of the input or at an error
+    done: bool,
 }
 
 
-#[derive(Debug)]
+/// Expression whose parsing is still in progress
+enum Pending {
+    /// Span of the opening paren and elements parsed so far of an open list
+    /// or vector
+    List(Span, Sequence, Vec<SExpression>, Vec<Positions>),
+    /// Span of a quote and name of the form it abbreviates, waiting for the
+    /// expression it applies to
+    Prefix(Span, &'static str),
+    /// Span of a datum comment, waiting for the expression to skip
+    Comment(Span),
+}
+
+
+/// Drop the quotes and datum comments waiting for an expression that was
+/// left out of the tree
+fn discard(open: &mut Vec<Pending>) {
+    while let Some(Pending::Prefix(..) | Pending::Comment(_)) = open.last() {
+        open.pop();
+    }
+}
+
+
+/// Kind of sequence opened by a paren
+enum Sequence {
+    List,
+    /// List where a dot was read, with its span and the number of elements
+    /// before it
+    Dotted(Span, usize),
+    Vector,
+    Bytevector,
+}
+
+impl Sequence {
+    /// Build the sequence out of its elements and their positions, once its
+    /// paren is closed
+    fn close(self, items: Vec<SExpression>, positions: Vec<Positions>) -> Result<(SExpression, Vec<Positions>)> {
+        match self {
+            Sequence::List => Ok((SExpression::List(items), positions)),
+            Sequence::Dotted(dot, before) if items.len() != before + 1 => Err(ParsingError::MisplacedDot(dot)),
+            Sequence::Dotted(..) => Ok(dotted(items, positions)),
+            Sequence::Vector => Ok((SExpression::Vector(items), positions)),
+            Sequence::Bytevector => items
+                .iter()
+                .zip(&positions)
+                .map(|(item, position)| match item {
+                    SExpression::Integer(n) => u8::try_from(*n).ok(),
+                    _ => None,
+                }
+                .ok_or_else(|| ParsingError::InvalidByte(position.span(), item.to_string())))
+                .collect::<Result<_>>()
+                .map(|bytes| (SExpression::Bytevector(bytes), positions)),
+        }
+    }
+}
+
+
+/// List of `items` ending with the last of them instead of the empty list,
+/// joined with its elements when it is a list itself, as `(a . (b c))` is
+/// `(a b c)`. The positions of the elements are joined the same way.
+fn dotted(mut items: Vec<SExpression>, mut positions: Vec<Positions>) -> (SExpression, Vec<Positions>) {
+    let (Some(mut tail), Some(mut tail_positions)) = (items.pop(), positions.pop()) else {
+        return (SExpression::List(items), positions);
+    };
+    // The elements of the tail are moved out, since a type dropping its
+    // children cannot be destructured
+    let last = match &mut tail {
+        SExpression::List(rest) => {
+            items.append(rest);
+            None
+        }
+        SExpression::DottedList(rest, last) => {
+            items.append(rest);
+            Some(std::mem::replace(last, Box::new(SExpression::List(vec![]))))
+        }
+        _ => {
+            positions.push(tail_positions);
+            return (SExpression::DottedList(items, Box::new(tail)), positions);
+        }
+    };
+    if let Positions::List { items: inner, .. } = &mut tail_positions {
+        positions.append(inner);
+    }
+    match last {
+        Some(last) => (SExpression::DottedList(items, last), positions),
+        None => (SExpression::List(items), positions),
+    }
+}
+
+
+#[derive(Debug, Clone)]
 pub enum SExpression {
+    Integer(i64),
     Number(f64),
+    Bool(bool),
+    Char(char),
     Str(String),
     Symbol(String),
     List(Vec<SExpression>),
+    /// List whose last pair ends with an element other than the empty list,
+    /// never a list itself
+    DottedList(Vec<SExpression>, Box<SExpression>),
+    Vector(Vec<SExpression>),
+    Bytevector(Vec<u8>),
+}
+
+/// Drop the elements iteratively, since dropping them recursively would
+/// overflow the stack on deeply nested lists
+impl Drop for SExpression {
+    fn drop(&mut self) {
+        let mut pending = vec![];
+        self.release(&mut pending);
+        // The elements are left without children, so dropping them does not
+        // recurse
+        while let Some(mut expr) = pending.pop() {
+            expr.release(&mut pending);
+        }
+    }
+}
+
+impl SExpression {
+    /// Move the elements of a sequence to `pending`
+    fn release(&mut self, pending: &mut Vec<SExpression>) {
+        match self {
+            SExpression::List(items) | SExpression::Vector(items) => pending.append(items),
+            SExpression::DottedList(items, tail) => {
+                pending.append(items);
+                pending.push(std::mem::replace(tail.as_mut(), SExpression::List(vec![])));
+            }
+            _ => (),
+        }
+    }
 }
 
 
-impl Parser {
-    pub fn init(toks: Vec<Token>) -> Self {
-        Parser {
-            tokens: toks,
-            cursor: 0,
+/// Part of an expression left to print
+enum Piece<'a> {
+    Expr(&'a SExpression),
+    Text(&'static str),
+}
+
+/// Lists are printed with an explicit stack, like the values, since they
+/// can be nested deeper than the native stack allows
+impl std::fmt::Display for SExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut pending = vec![Piece::Expr(self)];
+        while let Some(piece) = pending.pop() {
+            let expr = match piece {
+                Piece::Expr(expr) => expr,
+                Piece::Text(text) => {
+                    write!(f, "{}", text)?;
+                    continue;
+                }
+            };
+            match expr {
+                SExpression::Integer(n) => write!(f, "{}", n)?,
+                SExpression::Number(n) => write_real(f, *n)?,
+                SExpression::Bool(b) => write!(f, "{}", if *b { "#t" } else { "#f" })?,
+                SExpression::Char(c) => write_char(f, *c)?,
+                SExpression::Str(s) => write_string(f, s)?,
+                SExpression::Symbol(s) => write!(f, "{}", s)?,
+                SExpression::List(items) => {
+                    let abbreviated = match items.as_slice() {
+                        [SExpression::Symbol(name), quoted @ SExpression::Symbol(symbol)] => {
+                            abbreviation(name, Some(symbol)).zip(Some(quoted))
+                        }
+                        [SExpression::Symbol(name), quoted] => {
+                            abbreviation(name, None).zip(Some(quoted))
+                        }
+                        _ => None,
+                    };
+                    match abbreviated {
+                        Some((prefix, quoted)) => {
+                            write!(f, "{}", prefix)?;
+                            pending.push(Piece::Expr(quoted));
+                        }
+                        None => {
+                            write!(f, "(")?;
+                            push_elements(&mut pending, items, None);
+                        }
+                    }
+                }
+                SExpression::DottedList(items, tail) => {
+                    write!(f, "(")?;
+                    push_elements(&mut pending, items, Some(tail));
+                }
+                SExpression::Vector(items) => {
+                    write!(f, "#(")?;
+                    push_elements(&mut pending, items, None);
+                }
+                SExpression::Bytevector(bytes) => write_sequence(f, "#u8(", bytes)?,
+            }
         }
+        Ok(())
     }
+}
+
+
+/// Push the elements of a sequence to print, separated by spaces and
+/// followed by its tail after a dot, if any, and by the closing paren
+fn push_elements<'a>(
+    pending: &mut Vec<Piece<'a>>,
+    items: &'a [SExpression],
+    tail: Option<&'a SExpression>,
+) {
+    pending.push(Piece::Text(")"));
+    if let Some(tail) = tail {
+        pending.push(Piece::Expr(tail));
+        pending.push(Piece::Text(" . "));
+    }
+    for (i, item) in items.iter().enumerate().rev() {
+        pending.push(Piece::Expr(item));
+        if i > 0 {
+            pending.push(Piece::Text(" "));
+        }
+    }
+}
 
-    pub fn parse(self: &mut Self) -> Result<SExpression> {
-        self.parse_expression()
+
+/// Prefix abbreviating a list of two elements starting with the symbol
+/// `name`, as `'x` does for `(quote x)`. `symbol` is the second element when
+/// it is a symbol, since a comma followed by one starting with `@` would read
+/// back as `,@`.
+pub fn abbreviation(name: &str, symbol: Option<&str>) -> Option<&'static str> {
+    match name {
+        "quote" => Some("'"),
+        "quasiquote" => Some("`"),
+        "unquote" if !symbol.is_some_and(|s| s.starts_with('@')) => Some(","),
+        "unquote-splicing" => Some(",@"),
+        _ => None,
+    }
+}
+
+
+/// Write a string between double quotes, escaping the chars that cannot
+/// appear literally so that it reads back the same
+pub fn write_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            c if c.is_control() => write!(f, "\\x{:x};", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Write the elements of a sequence separated by spaces, between `open`
+/// and a closing paren
+pub fn write_sequence<T: std::fmt::Display>(
+    f: &mut std::fmt::Formatter<'_>,
+    open: &str,
+    items: impl IntoIterator<Item = T>,
+) -> std::fmt::Result {
+    write!(f, "{}", open)?;
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    write!(f, ")")
+}
+
+/// Write a character so that it reads back the same, by name when it is
+/// not printable
+pub fn write_char(f: &mut std::fmt::Formatter<'_>, c: char) -> std::fmt::Result {
+    match c {
+        '\u{7}' => write!(f, "#\\alarm"),
+        '\u{8}' => write!(f, "#\\backspace"),
+        '\u{7f}' => write!(f, "#\\delete"),
+        '\u{1b}' => write!(f, "#\\escape"),
+        '\n' => write!(f, "#\\newline"),
+        '\0' => write!(f, "#\\null"),
+        '\r' => write!(f, "#\\return"),
+        ' ' => write!(f, "#\\space"),
+        '\t' => write!(f, "#\\tab"),
+        c if c.is_control() || c.is_whitespace() => write!(f, "#\\x{:x}", c as u32),
+        c => write!(f, "#\\{}", c),
+    }
+}
+
+/// Write an inexact number so that it reads back as inexact, even when it
+/// is integral. Very large and very small numbers are written with an
+/// exponent, as `1e22` and `1.5e-10`.
+pub fn write_real(f: &mut std::fmt::Formatter<'_>, n: f64) -> std::fmt::Result {
+    if n.is_nan() {
+        write!(f, "+nan.0")
+    } else if n.is_infinite() {
+        write!(f, "{}inf.0", if n > 0.0 { "+" } else { "-" })
+    } else {
+        // The debug format keeps `.0` on integral numbers in positional form
+        write!(f, "{:?}", n)
+    }
+}
+
+
+#[derive(Debug, Clone)]
+/// Source spans of a parsed expression, mirroring the shape of its
+/// `SExpression` so that the syntax tree stays free of them
+pub enum Positions {
+    Atom(Span),
+    /// Spans of a list, a vector or a bytevector, and of each element
+    List {
+        /// Opening paren, along with the `#` or `#u8` before it, or the
+        /// quote abbreviating the list
+        open: Span,
+        /// Closing paren, `None` for the lists abbreviated by a quote
+        close: Option<Span>,
+        items: Vec<Positions>,
+    },
+}
+
+impl Positions {
+    /// Region of the source covered by the expression
+    pub fn span(&self) -> Span {
+        match self {
+            Positions::Atom(span) => *span,
+            Positions::List { open, .. } => {
+                // A list abbreviated by a quote ends with the quoted
+                // expression, which may be abbreviated too
+                let mut last = self;
+                let end = loop {
+                    match last {
+                        Positions::Atom(span) | Positions::List { close: Some(span), .. } => {
+                            break *span
+                        }
+                        Positions::List { open, items, .. } => match items.last() {
+                            Some(item) => last = item,
+                            None => break *open,
+                        },
+                    }
+                };
+                open.to(end)
+            }
+        }
     }
 
-    fn parse_expression(self: &mut Self) -> Result<SExpression> {
-        let res = match self.tokens[self.cursor] {
-            Token::OpenParen => self.parse_list(),
-            Token::CloseParen => Err(
-                ParsingError(String::from("closing parent without opening it"))
-            ),
-            _ => self.parse_atom(),
+    /// Position where the expression starts
+    pub fn start(&self) -> Position {
+        match self {
+            Positions::Atom(span) | Positions::List { open: span, .. } => span.position,
+        }
+    }
+}
+
+/// Drop the positions of the elements iteratively, like the expressions
+impl Drop for Positions {
+    fn drop(&mut self) {
+        let Positions::List { items, .. } = self else { return };
+        let mut pending = std::mem::take(items);
+        while let Some(mut positions) = pending.pop() {
+            if let Positions::List { items, .. } = &mut positions {
+                pending.append(items);
+            }
+        }
+    }
+}
+
+
+/// The parser yields every top-level expression of the input along with the
+/// spans of all its nodes, stopping after the first error
+impl<R: Read> Iterator for Parser<R> {
+    type Item = Result<(SExpression, Positions)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let parsed = self.parse_expression().transpose();
+        self.done = !matches!(parsed, Some(Ok(_)));
+        parsed
+    }
+}
+
+
+impl<R: Read> Parser<R> {
+    pub fn init(tokens: Lexer<R>) -> Self {
+        Parser {
+            tokens,
+            last: Span::default(),
+            errors: None,
+            done: false,
+        }
+    }
+
+    /// Parse all the expressions of the input, recovering from the errors
+    /// instead of stopping at the first one, and return all of them
+    pub fn diagnose(mut self) -> Vec<ParsingError> {
+        self.errors = Some(vec![]);
+        let fatal = loop {
+            match self.parse_expression() {
+                Ok(Some(_)) => (),
+                Ok(None) => break None,
+                Err(error) => break Some(error),
+            }
         };
-        self.cursor += 1;
-        res
+        let mut errors = self.errors.take().unwrap_or_default();
+        errors.extend(fatal);
+        errors
+    }
+
+    /// Fail with `error`, unless recovering from the errors, where it is
+    /// only collected
+    fn report(&mut self, error: ParsingError) -> Result<()> {
+        match &mut self.errors {
+            Some(errors) => {
+                errors.push(error);
+                Ok(())
+            }
+            None => Err(error),
+        }
     }
 
-    fn parse_list(self: &mut Self) -> Result<SExpression> {
-        self.cursor += 1; // consume open paren.
-        let mut res: Vec<SExpression> = vec![];
+    /// Parse an expression keeping the lists still open in an explicit
+    /// stack, so deeply nested input cannot overflow the native one. Return
+    /// `None` when the input is over.
+    fn parse_expression(&mut self) -> Result<Option<(SExpression, Positions)>> {
+        let mut open: Vec<Pending> = vec![];
+        // Errors reported before the expression, to tell whether it has some
+        let reported = self.errors.as_ref().map_or(0, Vec::len);
         loop {
-            if self.tokens[self.cursor] == Token::CloseParen || self.tokens[self.cursor] == Token::End {
-                return Ok(SExpression::List(res))
+            let (token, span) = match self.next_token() {
+                Ok(token) => token,
+                // The lexer resumes after the malformed token, which is left
+                // out of the tree along with the quotes waiting for it
+                Err(error) => {
+                    self.report(error)?;
+                    discard(&mut open);
+                    continue;
+                }
+            };
+            if matches!(token, Token::CloseParen | Token::End) {
+                // Quotes and datum comments not followed by an expression
+                loop {
+                    let error = match open.last() {
+                        Some(&Pending::Comment(start)) => ParsingError::UnterminatedDatumComment(start),
+                        Some(&Pending::Prefix(start, _)) => ParsingError::MissingQuoted(start),
+                        _ => break,
+                    };
+                    open.pop();
+                    self.report(error)?;
+                }
             }
-            let exp = self.parse_expression()?;
-            res.push(exp);
+            let (mut expr, mut positions) = match token {
+                // When recovering from an error in the expression, a paren at
+                // the start of a line within a list is taken as the beginning
+                // of a new top-level expression
+                Token::OpenParen
+                    if span.position.column == 1
+                        && self.errors.as_ref().is_some_and(|errors| errors.len() > reported)
+                        && open.iter().any(|pending| matches!(pending, Pending::List(..))) =>
+                {
+                    // The outermost list is reported, it may follow a quote
+                    let outermost = open.iter().find_map(|pending| match pending {
+                        Pending::List(start, ..) => Some(*start),
+                        _ => None,
+                    });
+                    if let Some(start) = outermost {
+                        self.report(ParsingError::UnclosedList(start))?;
+                    }
+                    open.clear();
+                    open.push(Pending::List(span, Sequence::List, vec![], vec![]));
+                    continue;
+                }
+                Token::OpenParen => {
+                    open.push(Pending::List(span, Sequence::List, vec![], vec![]));
+                    continue;
+                }
+                Token::OpenVector => {
+                    open.push(Pending::List(span, Sequence::Vector, vec![], vec![]));
+                    continue;
+                }
+                Token::OpenBytevector => {
+                    open.push(Pending::List(span, Sequence::Bytevector, vec![], vec![]));
+                    continue;
+                }
+                Token::DatumComment => {
+                    open.push(Pending::Comment(span));
+                    continue;
+                }
+                Token::Quote => {
+                    open.push(Pending::Prefix(span, "quote"));
+                    continue;
+                }
+                Token::Backquote => {
+                    open.push(Pending::Prefix(span, "quasiquote"));
+                    continue;
+                }
+                Token::Comma => {
+                    open.push(Pending::Prefix(span, "unquote"));
+                    continue;
+                }
+                Token::CommaAt => {
+                    open.push(Pending::Prefix(span, "unquote-splicing"));
+                    continue;
+                }
+                // A dot follows at least one element of a list, and only once
+                Token::Dot => {
+                    match open.last_mut() {
+                        Some(Pending::List(_, sequence @ Sequence::List, res, _)) if !res.is_empty() => {
+                            *sequence = Sequence::Dotted(span, res.len());
+                        }
+                        _ => {
+                            self.report(ParsingError::MisplacedDot(span))?;
+                            discard(&mut open);
+                        }
+                    }
+                    continue;
+                }
+                Token::CloseParen => match open.pop() {
+                    Some(Pending::List(start, sequence, res, positions)) => {
+                        match sequence.close(res, positions) {
+                            Ok((expr, positions)) => (
+                                expr,
+                                Positions::List { open: start, close: Some(span), items: positions },
+                            ),
+                            Err(error) => {
+                                self.report(error)?;
+                                discard(&mut open);
+                                continue;
+                            }
+                        }
+                    }
+                    _ => {
+                        self.report(ParsingError::UnexpectedCloseParen(span))?;
+                        continue;
+                    }
+                },
+                Token::End => {
+                    // Only the innermost list left open is reported
+                    if let Some(&Pending::List(start, ..)) = open.last() {
+                        self.report(ParsingError::UnclosedList(start))?;
+                    }
+                    return Ok(None);
+                }
+                Token::String(s) => (SExpression::Str(s), Positions::Atom(span)),
+                Token::Symbol(s) => (SExpression::Symbol(s), Positions::Atom(span)),
+                Token::Integer(n) => (SExpression::Integer(n), Positions::Atom(span)),
+                Token::Number(n) => (SExpression::Number(n), Positions::Atom(span)),
+                Token::Bool(b) => (SExpression::Bool(b), Positions::Atom(span)),
+                Token::Char(c) => (SExpression::Char(c), Positions::Atom(span)),
+            };
+            // Wrap the expression in the quotes preceding it, then add it to
+            // the innermost list unless it is commented out
+            loop {
+                match open.last_mut() {
+                    Some(Pending::Comment(_)) => {
+                        open.pop();
+                        break;
+                    }
+                    Some(&mut Pending::Prefix(start, name)) => {
+                        open.pop();
+                        expr = SExpression::List(vec![SExpression::Symbol(String::from(name)), expr]);
+                        positions = Positions::List {
+                            open: start,
+                            close: None,
+                            items: vec![Positions::Atom(start), positions],
+                        };
+                    }
+                    Some(Pending::List(_, _, res, inner)) => {
+                        res.push(expr);
+                        inner.push(positions);
+                        break;
+                    }
+                    None => return Ok(Some((expr, positions))),
+                }
+            }
+        }
+    }
+
+    /// Pull the next token from the lexer. Once the input is over, keep
+    /// returning `Token::End`.
+    fn next_token(&mut self) -> Result<(Token, Span)> {
+        match self.tokens.next() {
+            Some(Ok((token, span))) => {
+                self.last = span;
+                Ok((token, span))
+            }
+            Some(Err(error)) => Err(error),
+            None => Ok((Token::End, self.last)),
         }
     }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+
+    /// Pieces of syntax glued together at random, to build inputs that go
+    /// deeper into the parser than random bytes
+    const PIECES: &[&str] = &[
+        "(", ")", "#(", "#u8(", "'", "`", ",", ",@", "#;", "#|", "|#", "\"", "\\", ";",
+        " ", "\n", "x", "1", "-2.5", "#x1F", "#e1.5", "#\\a", "#\\x3bb", "#t", "é", ".",
+        "#", "\\x41;", "\u{fffd}", "\u{1F600}",
+    ];
+
+    /// Random input, made of random bytes half of the time, and of pieces of
+    /// syntax otherwise
+    fn input(random: &mut Random) -> Vec<u8> {
+        if random.below(2) == 0 {
+            (0..random.below(200)).map(|_| random.next() as u8).collect()
+        } else {
+            (0..random.below(60)).flat_map(|_| random.pick(PIECES).bytes()).collect()
+        }
+    }
+
+    #[test]
+    fn random_input_never_panics() {
+        let mut random = Random::new(0x5EED);
+        for _ in 0..20_000 {
+            let source = input(&mut random);
+            let mut failed = false;
+            for parsed in Parser::init(Lexer::new(source.as_slice())) {
+                if let Err(error) = parsed {
+                    error.render("input", &source);
+                    failed = true;
+                }
+            }
+            let errors = Parser::init(Lexer::new(source.as_slice())).diagnose();
+            for error in &errors {
+                error.render("input", &source);
+            }
+            // Recovering from the errors never hides the first one
+            assert!(!failed || !errors.is_empty(), "{:?}", String::from_utf8_lossy(&source));
+        }
+    }
+
+    #[test]
+    fn dotted_lists() {
+        let parse = |source: &str| -> Vec<String> {
+            Parser::init(Lexer::new(source.as_bytes()))
+                .map(|parsed| parsed.map_or_else(|e| e.message(), |(expr, _)| expr.to_string()))
+                .collect()
+        };
+        assert_eq!(parse("(a . b)"), ["(a . b)"]);
+        assert_eq!(parse("(a b . (c . (d)))"), ["(a b c d)"]);
+        assert_eq!(parse("(a . 'b)"), ["(a quote b)"]);
+        assert_eq!(parse("(a .b)"), ["(a .b)"]);
+        for source in ["(. a)", "(a .)", "(a . b c)", "(a . b . c)", ".", "#(a . b)", "(a ' . b)"] {
+            assert_eq!(parse(source), ["misplaced '.'"], "{}", source);
+        }
+    }
+
+    /// Byte ranges of the nodes of the single expression of `source`, as
+    /// `open[items]close` for lists, with `-` when there is no closing paren
+    fn layout(source: &str) -> String {
+        fn walk(positions: &Positions) -> String {
+            match positions {
+                Positions::Atom(span) => format!("{}..{}", span.start, span.end),
+                Positions::List { open, close, items } => {
+                    let items: Vec<_> = items.iter().map(walk).collect();
+                    let close = close.map_or(String::from("-"), |span| walk(&Positions::Atom(span)));
+                    format!("{}[{}]{}", walk(&Positions::Atom(*open)), items.join(" "), close)
+                }
+            }
+        }
+        let parsed: Vec<_> = Parser::init(Lexer::new(source.as_bytes())).collect();
+        match parsed.as_slice() {
+            [Ok((_, positions))] => walk(positions),
+            _ => panic!("{:?}", parsed),
+        }
+    }
+
+    /// Byte range covered by the single expression of `source`
+    fn extent(source: &str) -> (usize, usize) {
+        let (_, positions) = Parser::init(Lexer::new(source.as_bytes())).next().unwrap().unwrap();
+        let span = positions.span();
+        (span.start, span.end)
+    }
+
+    #[test]
+    fn positions_of_lists_and_vectors() {
+        assert_eq!(layout("(a bc)"), "0..1[1..2 3..5]5..6");
+        assert_eq!(layout("(f (g) ())"), "0..1[1..2 3..4[4..5]5..6 7..8[]8..9]9..10");
+        assert_eq!(layout("#(1 2)"), "0..2[2..3 4..5]5..6");
+        assert_eq!(layout("#u8(1 2)"), "0..4[4..5 6..7]7..8");
+        assert_eq!(extent("  (a\n  b)  "), (2, 9));
+        assert_eq!(extent("#u8(1)"), (0, 6));
+        let mut parser = Parser::init(Lexer::new("(a\n  b)".as_bytes()));
+        let (_, positions) = parser.next().unwrap().unwrap();
+        let Positions::List { items, .. } = &positions else { panic!("not a list") };
+        let Position { line, column } = items[1].start();
+        assert_eq!((line, column), (2, 3));
+    }
+
+    #[test]
+    fn positions_of_quotes() {
+        // The quote stands for the keyword, and the list ends with the quoted
+        // expression
+        assert_eq!(layout("'x"), "0..1[0..1 1..2]-");
+        assert_eq!(layout("'(a)"), "0..1[0..1 1..2[2..3]3..4]-");
+        assert_eq!(layout("`(a ,@b)"), "0..1[0..1 1..2[2..3 4..6[4..6 6..7]-]7..8]-");
+        assert_eq!(extent("'(a)"), (0, 4));
+        assert_eq!(extent("',x"), (0, 3));
+    }
+
+    #[test]
+    fn positions_of_dotted_lists() {
+        assert_eq!(layout("(a . b)"), "0..1[1..2 5..6]6..7");
+        // The elements of a list after the dot are joined to the others
+        assert_eq!(layout("(a . (b c))"), "0..1[1..2 6..7 8..9]10..11");
+        assert_eq!(layout("(a . (b . c))"), "0..1[1..2 6..7 10..11]12..13");
+        assert_eq!(layout("(a . 'b)"), "0..1[1..2 5..6 6..7]7..8");
+    }
+
+    #[test]
+    fn diagnose_valid_input() {
+        let source = "(define table '(\n(a 1)\n(b 2)))\n(define (f)\n  #;(g)\n(car table))\n";
+        assert!(Parser::init(Lexer::new(source.as_bytes())).diagnose().is_empty());
+    }
+
+    #[test]
+    fn diagnose_all_the_typos() {
+        let source = include_str!("../programs/typos.rlisp");
+        let errors = Parser::init(Lexer::new(source.as_bytes())).diagnose();
+        let found: Vec<_> = errors
+            .iter()
+            .map(|error| {
+                let position = error.span().map(|span| span.position);
+                (error.message(), position.map(|p| (p.line, p.column)))
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (String::from("invalid number `1x`"), Some((4, 8))),
+                (String::from("invalid escape sequence in string"), Some((5, 16))),
+                (String::from("invalid character `#\\bogus`"), Some((6, 7))),
+                (String::from("invalid byte `300` in bytevector"), Some((6, 21))),
+                (String::from("closing paren without opening it"), Some((7, 1))),
+                (String::from("unclosed '('"), Some((8, 1))),
+            ]
+        );
+    }
+
+    #[test]
+    fn diagnose_resumes_after_an_error_at_the_next_line() {
+        let source = "(define (f x)\n  (+ x 1x)\n(define y 2)\n(define z\n(list 3)";
+        let errors = Parser::init(Lexer::new(source.as_bytes())).diagnose();
+        let found: Vec<_> = errors
+            .iter()
+            .map(|error| (error.message(), error.span().map(|span| span.position.line)))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (String::from("invalid number `1x`"), Some(2)),
+                (String::from("unclosed '('"), Some(1)),
+                (String::from("unclosed '('"), Some(4)),
+            ]
+        );
+    }
 
-    fn parse_atom(self: &mut Self) -> Result<SExpression> {
-        match &self.tokens[self.cursor] {
-            Token::String(s) => Ok(SExpression::Str(s.clone())),
-            Token::Symbol(s) => Ok(SExpression::Symbol(s.clone())),
-            Token::Number(n) => Ok(SExpression::Number(*n)),
-            _ => Err(ParsingError(format!("{:?}", &self.tokens[self.cursor]))),
+    #[test]
+    fn deep_nesting_is_read_printed_and_dropped() {
+        let depth = 100_000;
+        for (open, close) in [("(a ", ")"), ("#(", ")"), ("(", " . b)"), ("'", "")] {
+            let source = format!("{}x{}", open.repeat(depth), close.repeat(depth));
+            let mut parser = Parser::init(Lexer::new(source.as_bytes()));
+            let (expr, positions) = parser.next().unwrap().unwrap();
+            assert_eq!(expr.to_string(), source);
+            assert_eq!(positions.span().end, source.len());
         }
     }
 }
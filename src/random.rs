@@ -0,0 +1,28 @@
+/// Pseudo-random generator of the property tests, seeded so that their
+/// failures can be reproduced
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        // The state of a xorshift generator must not be zero
+        Random(seed | 1)
+    }
+
+    /// Next number of the xorshift* sequence
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Number between 0 and `n` excluded
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Element of `items` taken at random
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
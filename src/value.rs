@@ -0,0 +1,711 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::eval::{Continuation, Control, Escape, EvalError, Result};
+use crate::expr::Lambda;
+use crate::lexer::Position;
+use crate::parser::{
+    abbreviation, write_char, write_real, write_sequence, write_string, SExpression,
+};
+
+#[derive(Debug, Clone)]
+/// Runtime value produced by the evaluator
+pub enum Value {
+    /// The empty list, also used as the false value
+    Nil,
+    Bool(bool),
+    /// Exact integer
+    Integer(i64),
+    /// Inexact real number
+    Number(f64),
+    Char(char),
+    Str(Rc<str>),
+    Symbol(Rc<str>),
+    /// Mutable cons cell shared between all its references
+    Pair(Rc<Pair>),
+    Vector(Rc<[Value]>),
+    Bytevector(Rc<[u8]>),
+    Builtin(Builtin),
+    /// Procedure defined in Lisp, closed over its defining environment
+    Closure(Rc<Closure>),
+    /// Builtin procedure that needs to access the evaluator state
+    Control(Control),
+    /// Continuation captured by `call/cc`
+    Continuation(Rc<Continuation>),
+    /// Escape-only continuation captured by `call/ec`
+    Escape(Escape),
+    /// Error object created by `error` or by a failing operation
+    Error(Rc<ErrorObject>),
+    /// Type of conditions created by `define-condition`
+    ConditionType(Rc<ConditionType>),
+    Condition(Rc<Condition>),
+}
+
+#[derive(Debug)]
+/// Heap allocated cons cell
+pub struct Pair {
+    pub car: RefCell<Value>,
+    pub cdr: RefCell<Value>,
+}
+
+impl Drop for Pair {
+    /// Drop the values held by the pair iteratively, since dropping them
+    /// recursively would overflow the stack on long or deeply nested lists
+    fn drop(&mut self) {
+        let mut pending = vec![];
+        release(&mut pending, self.car.get_mut());
+        release(&mut pending, self.cdr.get_mut());
+        while let Some(mut value) = pending.pop() {
+            // The contents are moved out only when nothing else refers to
+            // them, so the value is then dropped without recursing
+            match &mut value {
+                Value::Pair(pair) => {
+                    if let Some(pair) = Rc::get_mut(pair) {
+                        release(&mut pending, pair.car.get_mut());
+                        release(&mut pending, pair.cdr.get_mut());
+                    }
+                }
+                Value::Vector(items) => {
+                    if let Some(items) = Rc::get_mut(items) {
+                        for item in items.iter_mut() {
+                            release(&mut pending, item);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Move `value` to the values left to drop when it may hold other values
+fn release(pending: &mut Vec<Value>, value: &mut Value) {
+    if matches!(value, Value::Pair(_) | Value::Vector(_)) {
+        pending.push(std::mem::replace(value, Value::Nil));
+    }
+}
+
+#[derive(Clone, Copy)]
+/// Procedure implemented in Rust
+pub struct Builtin {
+    pub name: &'static str,
+    pub func: fn(&[Value]) -> Result<Value>,
+}
+
+#[derive(Debug)]
+/// Lambda along with the environment where it was evaluated
+pub struct Closure {
+    pub lambda: Rc<Lambda>,
+    pub env: Environment,
+}
+
+#[derive(Debug)]
+/// Description of a failure that can be raised and handled
+pub struct ErrorObject {
+    pub message: Rc<str>,
+    /// List of the values the message refers to
+    pub irritants: Value,
+    /// Where the failing expression starts in the source, when known
+    pub position: Option<Position>,
+}
+
+impl std::fmt::Display for ErrorObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Ok(irritants) = self.irritants.to_vec() {
+            for irritant in irritants {
+                write!(f, " {}", irritant)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+/// Type of conditions, along with the type it extends
+pub struct ConditionType {
+    pub name: Rc<str>,
+    pub parent: Option<Rc<ConditionType>>,
+    /// Names of the slots of its conditions, the inherited ones first
+    pub slots: Vec<Rc<str>>,
+}
+
+impl ConditionType {
+    /// Check whether the type is `other` or extends it
+    pub fn is_subtype(&self, other: &ConditionType) -> bool {
+        let mut kind = self;
+        loop {
+            if std::ptr::eq(kind, other) {
+                return true;
+            }
+            match &kind.parent {
+                Some(parent) => kind = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Condition signalled to the handlers established by `handler-bind`
+pub struct Condition {
+    pub kind: Rc<ConditionType>,
+    /// Value of each slot of the type
+    pub values: Vec<Value>,
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind.name)?;
+        for (slot, value) in self.kind.slots.iter().zip(&self.values) {
+            write!(f, " {}: {}", slot, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+impl Value {
+    /// Allocate a new cons cell
+    pub fn cons(car: Value, cdr: Value) -> Value {
+        Value::Pair(Rc::new(Pair {
+            car: RefCell::new(car),
+            cdr: RefCell::new(cdr),
+        }))
+    }
+
+    /// Build a proper list from the given values
+    pub fn list(items: Vec<Value>) -> Value {
+        items
+            .into_iter()
+            .rev()
+            .fold(Value::Nil, |tail, item| Value::cons(item, tail))
+    }
+
+    /// Collect the elements of a proper list, failing on improper ones
+    pub fn to_vec(&self) -> Result<Vec<Value>> {
+        let mut items = vec![];
+        let mut current = self.clone();
+        loop {
+            match current {
+                Value::Nil => return Ok(items),
+                Value::Pair(pair) => {
+                    items.push(pair.car.borrow().clone());
+                    let next = pair.cdr.borrow().clone();
+                    current = next;
+                }
+                _ => return Err(EvalError::new(format!("{} is not a proper list", self))),
+            }
+        }
+    }
+}
+
+impl Value {
+    /// Every value is true except `nil` and the false boolean
+    pub fn is_true(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// Check whether two values are the same object. Atoms are compared by
+    /// value, while heap objects must be the same allocation.
+    pub fn eqv(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => Rc::ptr_eq(a, b),
+            (Value::Pair(a), Value::Pair(b)) => Rc::ptr_eq(a, b),
+            (Value::Vector(a), Value::Vector(b)) => Rc::ptr_eq(a, b),
+            (Value::Bytevector(a), Value::Bytevector(b)) => Rc::ptr_eq(a, b),
+            (Value::Builtin(a), Value::Builtin(b)) => a.name == b.name,
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Control(a), Value::Control(b)) => a == b,
+            (Value::Continuation(a), Value::Continuation(b)) => Rc::ptr_eq(a, b),
+            (Value::Escape(a), Value::Escape(b)) => a == b,
+            (Value::Error(a), Value::Error(b)) => Rc::ptr_eq(a, b),
+            (Value::ConditionType(a), Value::ConditionType(b)) => Rc::ptr_eq(a, b),
+            (Value::Condition(a), Value::Condition(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Step of the conversion of syntax into data
+enum Conversion<'a> {
+    Expr(&'a SExpression),
+    /// Build a list, an improper one or a vector out of the values of its
+    /// last elements, which are converted before
+    List(usize),
+    DottedList(usize),
+    Vector(usize),
+}
+
+/// Quoting turns syntax into data. Lists are converted with an explicit
+/// stack, since they can be nested deeper than the native stack allows.
+impl From<&SExpression> for Value {
+    fn from(expr: &SExpression) -> Self {
+        let mut pending = vec![Conversion::Expr(expr)];
+        let mut values = vec![];
+        while let Some(step) = pending.pop() {
+            let value = match step {
+                Conversion::Expr(expr) => match expr {
+                    SExpression::Integer(n) => Value::Integer(*n),
+                    SExpression::Number(n) => Value::Number(*n),
+                    SExpression::Bool(b) => Value::Bool(*b),
+                    SExpression::Char(c) => Value::Char(*c),
+                    SExpression::Str(s) => Value::Str(s.as_str().into()),
+                    SExpression::Symbol(s) => Value::Symbol(s.as_str().into()),
+                    SExpression::Bytevector(bytes) => Value::Bytevector(bytes.as_slice().into()),
+                    SExpression::List(items) => {
+                        pending.push(Conversion::List(items.len()));
+                        pending.extend(items.iter().rev().map(Conversion::Expr));
+                        continue;
+                    }
+                    SExpression::DottedList(items, tail) => {
+                        pending.push(Conversion::DottedList(items.len()));
+                        pending.push(Conversion::Expr(tail));
+                        pending.extend(items.iter().rev().map(Conversion::Expr));
+                        continue;
+                    }
+                    SExpression::Vector(items) => {
+                        pending.push(Conversion::Vector(items.len()));
+                        pending.extend(items.iter().rev().map(Conversion::Expr));
+                        continue;
+                    }
+                },
+                Conversion::List(len) => Value::list(values.split_off(values.len() - len)),
+                Conversion::DottedList(len) => {
+                    let tail = values.pop().expect("Never happen!");
+                    let items = values.split_off(values.len() - len);
+                    items
+                        .into_iter()
+                        .rev()
+                        .fold(tail, |cdr, car| Value::cons(car, cdr))
+                }
+                Conversion::Vector(len) => {
+                    Value::Vector(values.split_off(values.len() - len).into())
+                }
+            };
+            values.push(value);
+        }
+        values.pop().expect("Never happen!")
+    }
+}
+
+/// Reading turns data back into syntax
+impl TryFrom<&Value> for SExpression {
+    type Error = EvalError;
+
+    fn try_from(value: &Value) -> Result<Self> {
+        match value {
+            Value::Nil => Ok(SExpression::List(vec![])),
+            Value::Bool(b) => Ok(SExpression::Bool(*b)),
+            Value::Integer(n) => Ok(SExpression::Integer(*n)),
+            Value::Number(n) => Ok(SExpression::Number(*n)),
+            Value::Char(c) => Ok(SExpression::Char(*c)),
+            Value::Str(s) => Ok(SExpression::Str(s.to_string())),
+            Value::Symbol(s) => Ok(SExpression::Symbol(s.to_string())),
+            Value::Pair(_) => {
+                let mut items = vec![];
+                let mut current = value.clone();
+                while let Value::Pair(pair) = current {
+                    items.push(SExpression::try_from(&*pair.car.borrow())?);
+                    let next = pair.cdr.borrow().clone();
+                    current = next;
+                }
+                Ok(match current {
+                    Value::Nil => SExpression::List(items),
+                    tail => SExpression::DottedList(items, Box::new(SExpression::try_from(&tail)?)),
+                })
+            }
+            Value::Vector(items) => Ok(SExpression::Vector(
+                items
+                    .iter()
+                    .map(SExpression::try_from)
+                    .collect::<Result<_>>()?,
+            )),
+            Value::Bytevector(bytes) => Ok(SExpression::Bytevector(bytes.to_vec())),
+            Value::Builtin(_)
+            | Value::Closure(_)
+            | Value::Control(_)
+            | Value::Continuation(_)
+            | Value::Escape(_)
+            | Value::Error(_)
+            | Value::ConditionType(_)
+            | Value::Condition(_) => Err(EvalError::new(format!("{} has no syntax", value))),
+        }
+    }
+}
+
+/// Values are written so that the reader can read them back
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.printed(true).fmt(f)
+    }
+}
+
+/// Value printed either by `write`, for the reader, or by `display`, for
+/// humans, which shows strings and chars as they are
+pub struct Printed<'a> {
+    value: &'a Value,
+    write: bool,
+}
+
+impl Value {
+    /// Print the value as `display` does
+    pub fn display(&self) -> Printed<'_> {
+        self.printed(false)
+    }
+
+    fn printed(&self, write: bool) -> Printed<'_> {
+        Printed { value: self, write }
+    }
+}
+
+/// Part of a value left to print
+enum Piece {
+    Value(Value),
+    /// Rest of a list whose first elements are already printed
+    Tail(Value),
+    Text(&'static str),
+}
+
+/// Lists and vectors are printed with an explicit stack, since the values
+/// built at runtime can be nested deeper than the native stack allows
+impl std::fmt::Display for Printed<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut pending = vec![Piece::Value(self.value.clone())];
+        while let Some(piece) = pending.pop() {
+            match piece {
+                Piece::Value(Value::Pair(pair)) => match abbreviated(&pair) {
+                    Some((prefix, quoted)) => {
+                        write!(f, "{}", prefix)?;
+                        pending.push(Piece::Value(quoted));
+                    }
+                    None => {
+                        write!(f, "(")?;
+                        pending.push(Piece::Tail(pair.cdr.borrow().clone()));
+                        pending.push(Piece::Value(pair.car.borrow().clone()));
+                    }
+                },
+                Piece::Value(Value::Vector(items)) => {
+                    write!(f, "#(")?;
+                    pending.push(Piece::Text(")"));
+                    for (i, item) in items.iter().enumerate().rev() {
+                        pending.push(Piece::Value(item.clone()));
+                        if i > 0 {
+                            pending.push(Piece::Text(" "));
+                        }
+                    }
+                }
+                Piece::Value(atom) => write_atom(f, &atom, self.write)?,
+                Piece::Tail(Value::Nil) => write!(f, ")")?,
+                Piece::Tail(Value::Pair(pair)) => {
+                    write!(f, " ")?;
+                    pending.push(Piece::Tail(pair.cdr.borrow().clone()));
+                    pending.push(Piece::Value(pair.car.borrow().clone()));
+                }
+                // Improper list, print the last element after a dot
+                Piece::Tail(last) => {
+                    write!(f, " . ")?;
+                    pending.push(Piece::Text(")"));
+                    pending.push(Piece::Value(last));
+                }
+                Piece::Text(text) => write!(f, "{}", text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Print a value holding no other value, which are printed the same by
+/// `write` and `display` except for strings, chars, booleans and the empty
+/// list, written the way the reader reads them back
+fn write_atom(f: &mut std::fmt::Formatter<'_>, value: &Value, write: bool) -> std::fmt::Result {
+    match value {
+        Value::Nil if write => write!(f, "()"),
+        Value::Bool(b) if write => write!(f, "{}", if *b { "#t" } else { "#f" }),
+        Value::Nil | Value::Bool(false) => write!(f, "nil"),
+        Value::Bool(true) => write!(f, "t"),
+        Value::Integer(n) => write!(f, "{}", n),
+        Value::Number(n) => write_real(f, *n),
+        Value::Char(c) if write => write_char(f, *c),
+        Value::Char(c) => write!(f, "{}", c),
+        Value::Str(s) if write => write_string(f, s),
+        Value::Str(s) => write!(f, "{}", s),
+        Value::Symbol(s) => write!(f, "{}", s),
+        Value::Bytevector(bytes) => write_sequence(f, "#u8(", bytes.iter()),
+        Value::Builtin(builtin) => write!(f, "#<builtin {}>", builtin.name),
+        Value::Closure(closure) => match &closure.lambda.name {
+            Some(name) => write!(f, "#<procedure {}>", name),
+            None => write!(f, "#<procedure>"),
+        },
+        Value::Control(control) => write!(f, "#<builtin {}>", control.name()),
+        Value::Continuation(_) => write!(f, "#<continuation>"),
+        Value::Escape(_) => write!(f, "#<escape continuation>"),
+        Value::Error(error) => write!(f, "#<error {}>", error),
+        Value::ConditionType(kind) => write!(f, "#<condition-type {}>", kind.name),
+        Value::Condition(condition) => write!(f, "#<condition {}>", condition),
+        // Lists and vectors are printed piece by piece by `Printed`
+        Value::Pair(_) | Value::Vector(_) => Ok(()),
+    }
+}
+
+/// Prefix and quoted value of a list that can be printed abbreviated, as
+/// `'x` for `(quote x)`
+fn abbreviated(pair: &Pair) -> Option<(&'static str, Value)> {
+    let name = match &*pair.car.borrow() {
+        Value::Symbol(name) => name.clone(),
+        _ => return None,
+    };
+    let rest = match &*pair.cdr.borrow() {
+        Value::Pair(rest) if matches!(*rest.cdr.borrow(), Value::Nil) => rest.clone(),
+        _ => return None,
+    };
+    let quoted = rest.car.borrow().clone();
+    let symbol = match &quoted {
+        Value::Symbol(symbol) => Some(&**symbol),
+        _ => None,
+    };
+    Some((abbreviation(&name, symbol)?, quoted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::random::Random;
+
+    /// Depth beyond which recursing on a value overflows the native stack
+    const DEEP: usize = 1_000_000;
+
+    #[test]
+    fn print_deeply_nested_lists() {
+        let mut nested = Value::Integer(1);
+        for _ in 0..DEEP {
+            nested = Value::list(vec![nested]);
+        }
+        let expected = format!("{}1{}", "(".repeat(DEEP), ")".repeat(DEEP));
+        assert!(nested.to_string() == expected);
+    }
+
+    #[test]
+    fn print_deeply_nested_vectors() {
+        let mut nested = Value::Integer(1);
+        for _ in 0..DEEP {
+            nested = Value::cons(Value::Vector(Rc::new([nested])), Value::Nil);
+        }
+        let expected = format!("{}1{}", "(#(".repeat(DEEP), "))".repeat(DEEP));
+        assert!(nested.to_string() == expected);
+    }
+
+    #[test]
+    fn print_improper_and_quoted_lists() {
+        let symbol = |name: &str| Value::Symbol(name.into());
+        let dotted = Value::cons(
+            Value::Integer(1),
+            Value::cons(Value::Integer(2), symbol("x")),
+        );
+        assert_eq!(dotted.to_string(), "(1 2 . x)");
+        let quoted = Value::list(vec![symbol("quote"), Value::list(vec![symbol("a")])]);
+        let vector = Value::Vector(Rc::new([quoted, Value::Nil]));
+        assert_eq!(vector.to_string(), "#('(a) ())");
+        assert_eq!(vector.display().to_string(), "#('(a) nil)");
+    }
+
+    #[test]
+    fn write_booleans_and_empty_list_readably() {
+        let values = Value::list(vec![Value::Bool(true), Value::Bool(false), Value::Nil]);
+        assert_eq!(values.to_string(), "(#t #f ())");
+        assert_eq!(values.display().to_string(), "(t nil nil)");
+    }
+
+    #[test]
+    fn procedures_have_no_syntax() {
+        let procedure = Value::Builtin(crate::builtins::LIST);
+        let error =
+            SExpression::try_from(&Value::list(vec![Value::Integer(1), procedure])).unwrap_err();
+        assert_eq!(error.message, "#<builtin list> has no syntax");
+    }
+
+    #[test]
+    fn write_reals_with_an_exponent_only_when_far_from_one() {
+        let written = |n: f64| Value::Number(n).to_string();
+        assert_eq!(written(1e22), "1e22");
+        assert_eq!(written(1.5e-10), "1.5e-10");
+        assert_eq!(written(5e-324), "5e-324");
+        assert_eq!(written(-2.5e300), "-2.5e300");
+        assert_eq!(written(1e15), "1000000000000000.0");
+        assert_eq!(written(3.0), "3.0");
+        assert_eq!(written(-0.0), "-0.0");
+        assert_eq!(written(0.1), "0.1");
+    }
+
+    /// Symbols that read back as symbols, some of them close to other syntax
+    const SYMBOLS: &[&str] = &[
+        "x",
+        "foo-bar",
+        "+",
+        "-",
+        "...",
+        "@a",
+        ".b",
+        "->x",
+        "λ",
+        "t",
+        "nil",
+        "quote",
+        "quasiquote",
+        "unquote",
+        "unquote-splicing",
+    ];
+
+    /// Random value nested at most `depth` times, made of the data the
+    /// reader can build
+    fn value(random: &mut Random, depth: usize) -> Value {
+        let kind = if depth == 0 {
+            random.below(7)
+        } else {
+            random.below(11)
+        };
+        match kind {
+            0 => random
+                .pick(&[Value::Nil, Value::Bool(true), Value::Bool(false)])
+                .clone(),
+            1 => Value::Integer(match random.below(3) {
+                0 => *random.pick(&[0, -1, i64::MIN, i64::MAX]),
+                1 => random.below(1000) as i64 - 500,
+                _ => random.next() as i64,
+            }),
+            2 => Value::Number(match random.below(3) {
+                0 => *random.pick(&[
+                    0.0,
+                    -0.0,
+                    1e300,
+                    5e-324,
+                    f64::NAN,
+                    f64::INFINITY,
+                    f64::NEG_INFINITY,
+                ]),
+                1 => (random.below(2000) as f64 - 1000.0) / 8.0,
+                _ => f64::from_bits(random.next()),
+            }),
+            3 => Value::Char(character(random)),
+            4 => Value::Str(
+                (0..random.below(8))
+                    .map(|_| character(random))
+                    .collect::<String>()
+                    .into(),
+            ),
+            5 => Value::Symbol((*random.pick(SYMBOLS)).into()),
+            6 => Value::Bytevector((0..random.below(5)).map(|_| random.next() as u8).collect()),
+            7 => Value::Vector(
+                (0..random.below(4))
+                    .map(|_| value(random, depth - 1))
+                    .collect(),
+            ),
+            // Quote forms, which are written abbreviated
+            8 => {
+                let name = *random.pick(&["quote", "quasiquote", "unquote", "unquote-splicing"]);
+                Value::list(vec![Value::Symbol(name.into()), value(random, depth - 1)])
+            }
+            // Proper and improper lists
+            _ => {
+                let items = (0..random.below(4) + 1)
+                    .map(|_| value(random, depth - 1))
+                    .collect::<Vec<_>>();
+                let tail = match random.below(2) {
+                    0 => Value::Nil,
+                    _ => value(random, depth - 1),
+                };
+                items
+                    .into_iter()
+                    .rev()
+                    .fold(tail, |cdr, car| Value::cons(car, cdr))
+            }
+        }
+    }
+
+    /// Random char, printable or not
+    fn character(random: &mut Random) -> char {
+        match random.below(3) {
+            0 => *random.pick(&[
+                'a', ' ', '\n', '\t', '\0', '\u{7f}', '"', '\\', '(', ';', '#', 'λ', '\u{a0}', '😀',
+            ]),
+            1 => char::from(random.below(128) as u8),
+            _ => char::from_u32(random.below(0x110000) as u32).unwrap_or('\u{fffd}'),
+        }
+    }
+
+    /// Structural equality, where NaN is the same as itself
+    fn same(a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (Value::Number(x), Value::Number(y)) => {
+                x.to_bits() == y.to_bits() || x.is_nan() && y.is_nan()
+            }
+            (Value::Str(x), Value::Str(y)) | (Value::Symbol(x), Value::Symbol(y)) => x == y,
+            (Value::Pair(x), Value::Pair(y)) => {
+                same(&x.car.borrow(), &y.car.borrow()) && same(&x.cdr.borrow(), &y.cdr.borrow())
+            }
+            (Value::Vector(x), Value::Vector(y)) => {
+                x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| same(x, y))
+            }
+            (Value::Bytevector(x), Value::Bytevector(y)) => x == y,
+            _ => a.eqv(b),
+        }
+    }
+
+    /// Read the single expression written in `text`
+    fn read(text: &str) -> SExpression {
+        let parsed: Vec<_> = Parser::init(Lexer::new(text.as_bytes())).collect();
+        match parsed.as_slice() {
+            [Ok((expr, _))] => expr.clone(),
+            _ => panic!(
+                "{:?} does not read back as one expression: {:?}",
+                text, parsed
+            ),
+        }
+    }
+
+    #[test]
+    fn written_values_read_back() {
+        let mut random = Random::new(0xC0FFEE);
+        for _ in 0..5_000 {
+            let original = value(&mut random, 5);
+            let written = original.to_string();
+            let expr = read(&written);
+            assert!(
+                same(&Value::from(&expr), &original),
+                "{} read back as {}",
+                written,
+                expr
+            );
+            // Converting the value back to syntax gives the same tree
+            let syntax = SExpression::try_from(&original).unwrap();
+            assert!(
+                same(&Value::from(&syntax), &original),
+                "{} converted to {}",
+                written,
+                syntax
+            );
+            // The syntax tree is written back the same way
+            let rewritten = expr.to_string();
+            assert!(
+                same(&Value::from(&read(&rewritten)), &original),
+                "{} read back from {}",
+                rewritten,
+                written
+            );
+        }
+    }
+}